@@ -92,6 +92,7 @@ where
             "GL_NV_vertex_attrib_integer_64bit",
             "GL_EXT_memory_object_win32",
             "GL_EXT_memory_object_fd",
+            "GL_EXT_semaphore_win32",
         ],
     );
 
@@ -1,6 +1,6 @@
 use std::ffi::c_void;
 
-use crate::{ImageCreateInfo, ImageFormat, InteropHandle};
+use crate::{ImageCreateInfo, ImageFormat, ImageLayout, InteropHandle};
 
 pub(crate) mod bindings {
     #![allow(clippy::all)]
@@ -12,14 +12,19 @@ lazy_static::lazy_static! {
         [
             (ImageFormat::Rgba8Unorm, bindings::RGBA8),
             (ImageFormat::Rgba8UnormSrgb, bindings::SRGB8_ALPHA8),
+            //Desktop OpenGL has no BGRA storage
 
             (ImageFormat::Rgb10a2Unorm, bindings::RGB10_A2),
+            (ImageFormat::Rg11b10Float, bindings::R11F_G11F_B10F),
 
+            (ImageFormat::Rgba16Unorm, bindings::RGBA16),
             (ImageFormat::Rgba16Float, bindings::RGBA16F),
 
             (ImageFormat::Rgba32Float, bindings::RGBA32F),
 
             (ImageFormat::Depth32Float, bindings::DEPTH_COMPONENT32F),
+            (ImageFormat::Depth32FloatStencil8, bindings::DEPTH32F_STENCIL8),
+            (ImageFormat::Depth24Plus, bindings::DEPTH_COMPONENT24),
             (ImageFormat::Depth24PlusStencil8, bindings::DEPTH24_STENCIL8),
 
             //ImageFormat::Depth24FloatPlusStencil8Uint
@@ -75,7 +80,69 @@ impl OpenGLInterop {
     ) -> GlResult<u32> {
         let mut texture = 0;
 
-        if create_info.layers == 1 {
+        if create_info.sample_count > 1 {
+            //Multisampled images have no mip chain, the Vulkan image uses the standard sample locations
+            unsafe {
+                if create_info.layers == 1 {
+                    self.gl
+                        .CreateTextures(bindings::TEXTURE_2D_MULTISAMPLE, 1, &mut texture);
+                    self.gl.TextureStorageMem2DMultisampleEXT(
+                        texture,
+                        create_info.sample_count as i32,
+                        create_info.format.to_gl().unwrap(),
+                        create_info.width as i32,
+                        create_info.height as i32,
+                        bindings::TRUE,
+                        mem_obj,
+                        offset,
+                    );
+                } else {
+                    self.gl
+                        .CreateTextures(bindings::TEXTURE_2D_MULTISAMPLE_ARRAY, 1, &mut texture);
+                    self.gl.TextureStorageMem3DMultisampleEXT(
+                        texture,
+                        create_info.sample_count as i32,
+                        create_info.format.to_gl().unwrap(),
+                        create_info.width as i32,
+                        create_info.height as i32,
+                        create_info.layers as i32,
+                        bindings::TRUE,
+                        mem_obj,
+                        offset,
+                    );
+                }
+            }
+        } else if create_info.faces == 6 {
+            //Cubemap faces are stored as consecutive layers, the same as in Vulkan
+            unsafe {
+                if create_info.layers == 1 {
+                    self.gl
+                        .CreateTextures(bindings::TEXTURE_CUBE_MAP, 1, &mut texture);
+                    self.gl.TextureStorageMem2DEXT(
+                        texture,
+                        create_info.mip_count as i32,
+                        create_info.format.to_gl().unwrap(),
+                        create_info.width as i32,
+                        create_info.height as i32,
+                        mem_obj,
+                        offset,
+                    );
+                } else {
+                    self.gl
+                        .CreateTextures(bindings::TEXTURE_CUBE_MAP_ARRAY, 1, &mut texture);
+                    self.gl.TextureStorageMem3DEXT(
+                        texture,
+                        create_info.mip_count as i32,
+                        create_info.format.to_gl().unwrap(),
+                        create_info.width as i32,
+                        create_info.height as i32,
+                        create_info.array_layers() as i32,
+                        mem_obj,
+                        offset,
+                    );
+                }
+            }
+        } else if create_info.layers == 1 {
             unsafe {
                 self.gl
                     .CreateTextures(bindings::TEXTURE_2D, 1, &mut texture);
@@ -112,6 +179,65 @@ impl OpenGLInterop {
             Ok(texture)
         }
     }
+
+    /// Whether GL_EXT_semaphore is available for the platform's handle type
+    pub fn supports_semaphores(&self) -> bool {
+        #[cfg(target_os = "windows")]
+        let import = &self.gl.ImportSemaphoreWin32HandleEXT;
+        #[cfg(target_os = "linux")]
+        let import = &self.gl.ImportSemaphoreFdEXT;
+        self.gl.SignalSemaphoreEXT.is_loaded() && import.is_loaded()
+    }
+
+    pub fn import_semaphore(&self, handle: InteropHandle) -> GlResult<u32> {
+        let mut semaphore = 0;
+
+        unsafe {
+            self.gl.GenSemaphoresEXT(1, &mut semaphore);
+            #[cfg(target_os = "windows")]
+            self.gl.ImportSemaphoreWin32HandleEXT(
+                semaphore,
+                bindings::HANDLE_TYPE_OPAQUE_WIN32_EXT,
+                handle,
+            );
+            #[cfg(target_os = "linux")]
+            self.gl
+                .ImportSemaphoreFdEXT(semaphore, bindings::HANDLE_TYPE_OPAQUE_FD_EXT, handle);
+
+            if self.gl.IsSemaphoreEXT(semaphore) == bindings::FALSE {
+                Err(self.gl.GetError())
+            } else {
+                Ok(semaphore)
+            }
+        }
+    }
+
+    /// Signals `semaphore` once every command so far is done with `texture`,
+    /// which is handed over in `layout`
+    pub fn signal_semaphore(&self, semaphore: u32, texture: u32, layout: ImageLayout) {
+        unsafe {
+            self.gl.SignalSemaphoreEXT(
+                semaphore,
+                0,
+                std::ptr::null(),
+                1,
+                &texture,
+                &layout.to_gl(),
+            );
+            //The signal has to reach the driver before anything can wait on it
+            self.gl.Flush();
+        }
+    }
+}
+
+impl ImageLayout {
+    pub fn to_gl(&self) -> u32 {
+        match self {
+            ImageLayout::General => bindings::LAYOUT_GENERAL_EXT,
+            ImageLayout::ShaderReadOnly => bindings::LAYOUT_SHADER_READ_ONLY_EXT,
+            ImageLayout::TransferSrc => bindings::LAYOUT_TRANSFER_SRC_EXT,
+        }
+    }
 }
 
 impl ImageFormat {
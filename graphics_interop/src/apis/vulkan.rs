@@ -1,6 +1,6 @@
 use ash::{prelude::VkResult, vk, Device, Instance};
 
-use crate::{ImageFormat, InteropHandle};
+use crate::{ImageFormat, ImageLayout, ImageUsage, InteropHandle};
 
 lazy_static::lazy_static! {
     static ref VK_FORMATS: bimap::BiHashMap<ImageFormat, vk::Format> = {
@@ -8,24 +8,74 @@ lazy_static::lazy_static! {
         [
             (ImageFormat::Rgba8Unorm, Format::R8G8B8A8_UNORM),
             (ImageFormat::Rgba8UnormSrgb, Format::R8G8B8A8_SRGB),
+            (ImageFormat::Bgra8Unorm, Format::B8G8R8A8_UNORM),
+            (ImageFormat::Bgra8UnormSrgb, Format::B8G8R8A8_SRGB),
 
-            (ImageFormat::Rgb10a2Unorm, Format::A2R10G10B10_UNORM_PACK32),
+            (ImageFormat::Rgb10a2Unorm, Format::A2B10G10R10_UNORM_PACK32),
+            (ImageFormat::Rg11b10Float, Format::B10G11R11_UFLOAT_PACK32),
 
+            (ImageFormat::Rgba16Unorm, Format::R16G16B16A16_UNORM),
             (ImageFormat::Rgba16Float, Format::R16G16B16A16_SFLOAT),
 
             (ImageFormat::Rgba32Float, Format::R32G32B32A32_SFLOAT),
 
             (ImageFormat::Depth32Float, Format::D32_SFLOAT),
+            (ImageFormat::Depth32FloatStencil8, Format::D32_SFLOAT_S8_UINT),
+            (ImageFormat::Depth24Plus, Format::X8_D24_UNORM_PACK32),
             (ImageFormat::Depth24PlusStencil8, Format::D24_UNORM_S8_UINT),
 
             //ImageFormat::Depth24FloatPlusStencil8Uint
             (ImageFormat::Depth16Unorm, Format::D16_UNORM),
+
+            (ImageFormat::Bgr10a2Unorm, Format::A2R10G10B10_UNORM_PACK32),
         ]
         .into_iter()
         .collect::<bimap::BiHashMap<_, _>>()
     };
 }
 
+impl ImageUsage {
+    /// The Vulkan usage flags, `MUTABLE_FORMAT` is a create flag instead
+    pub fn to_vk(&self) -> vk::ImageUsageFlags {
+        [
+            (
+                ImageUsage::COLOR_ATTACHMENT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            ),
+            (
+                ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ),
+            (ImageUsage::STORAGE, vk::ImageUsageFlags::STORAGE),
+            (ImageUsage::TRANSFER_SRC, vk::ImageUsageFlags::TRANSFER_SRC),
+            (ImageUsage::TRANSFER_DST, vk::ImageUsageFlags::TRANSFER_DST),
+            (ImageUsage::SAMPLED, vk::ImageUsageFlags::SAMPLED),
+            (
+                ImageUsage::INPUT_ATTACHMENT,
+                vk::ImageUsageFlags::INPUT_ATTACHMENT,
+            ),
+        ]
+        .into_iter()
+        .filter(|(usage, _)| self.contains(*usage))
+        .fold(vk::ImageUsageFlags::empty(), |flags, (_, vk_usage)| {
+            flags | vk_usage
+        })
+    }
+}
+
+impl crate::ImageCreateInfo {
+    pub fn vk_create_flags(&self) -> vk::ImageCreateFlags {
+        let mut flags = vk::ImageCreateFlags::empty();
+        if self.faces == 6 {
+            flags |= vk::ImageCreateFlags::CUBE_COMPATIBLE;
+        }
+        if self.usage.contains(ImageUsage::MUTABLE_FORMAT) {
+            flags |= vk::ImageCreateFlags::MUTABLE_FORMAT;
+        }
+        flags
+    }
+}
+
 pub fn needed_instance_extensions() -> Vec<*const i8> {
     vec![
         vk::KhrExternalMemoryCapabilitiesFn::name().as_ptr(),
@@ -40,6 +90,11 @@ pub fn needed_device_extensions() -> Vec<*const i8> {
         vk::KhrExternalMemoryWin32Fn::name().as_ptr(),
         #[cfg(target_os = "linux")]
         vk::KhrExternalMemoryFdFn::name().as_ptr(),
+        vk::KhrExternalSemaphoreFn::name().as_ptr(),
+        #[cfg(target_os = "windows")]
+        vk::KhrExternalSemaphoreWin32Fn::name().as_ptr(),
+        #[cfg(target_os = "linux")]
+        vk::KhrExternalSemaphoreFdFn::name().as_ptr(),
     ]
 }
 
@@ -54,6 +109,12 @@ pub struct VulkanInterop {
 
     #[cfg(target_os = "linux")]
     khr_external_memory: vk::KhrExternalMemoryFdFn,
+
+    #[cfg(target_os = "windows")]
+    khr_external_semaphore: vk::KhrExternalSemaphoreWin32Fn,
+
+    #[cfg(target_os = "linux")]
+    khr_external_semaphore: vk::KhrExternalSemaphoreFdFn,
 }
 
 impl VulkanInterop {
@@ -61,24 +122,25 @@ impl VulkanInterop {
         let device_memory_properties =
             unsafe { instance.get_physical_device_memory_properties(physical_device) };
 
-        let khr_external_memory = unsafe {
-            let load_fn = |name: &std::ffi::CStr| {
-                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
-            };
-            #[cfg(target_os = "windows")]
-            {
-                vk::KhrExternalMemoryWin32Fn::load(load_fn)
-            }
-            #[cfg(target_os = "linux")]
-            {
-                vk::KhrExternalMemoryFdFn::load(load_fn)
-            }
+        let load_fn = |name: &std::ffi::CStr| unsafe {
+            std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
         };
+        #[cfg(target_os = "windows")]
+        let (khr_external_memory, khr_external_semaphore) = (
+            vk::KhrExternalMemoryWin32Fn::load(load_fn),
+            vk::KhrExternalSemaphoreWin32Fn::load(load_fn),
+        );
+        #[cfg(target_os = "linux")]
+        let (khr_external_memory, khr_external_semaphore) = (
+            vk::KhrExternalMemoryFdFn::load(load_fn),
+            vk::KhrExternalSemaphoreFdFn::load(load_fn),
+        );
 
         Self {
             device_memory_properties,
             device: device.clone(),
             khr_external_memory,
+            khr_external_semaphore,
         }
     }
 
@@ -96,6 +158,7 @@ impl VulkanInterop {
 
         let create_info = vk::ImageCreateInfo {
             p_next: &export_info as *const _ as _,
+            flags: image_create_info.vk_create_flags(),
             image_type: vk::ImageType::TYPE_2D,
             format: *VK_FORMATS.get_by_left(&image_create_info.format).unwrap(),
             extent: vk::Extent3D {
@@ -104,13 +167,10 @@ impl VulkanInterop {
                 depth: 1,
             },
             mip_levels: image_create_info.mip_count,
-            array_layers: image_create_info.layers,
-            samples: vk::SampleCountFlags::TYPE_1,
+            array_layers: image_create_info.array_layers(),
+            samples: vk::SampleCountFlags::from_raw(image_create_info.sample_count),
             tiling: vk::ImageTiling::OPTIMAL,
-            usage: vk::ImageUsageFlags::TRANSFER_DST
-                | vk::ImageUsageFlags::SAMPLED
-                | vk::ImageUsageFlags::TRANSFER_SRC
-                | vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            usage: image_create_info.usage.to_vk(),
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             ..Default::default()
         };
@@ -204,6 +264,75 @@ impl VulkanInterop {
             Ok(handle)
         }
     }
+
+    /// Creates a binary semaphore which can be exported with `get_external_semaphore_handle`
+    pub fn create_external_semaphore(&self) -> VkResult<vk::Semaphore> {
+        let export_info = vk::ExportSemaphoreCreateInfo {
+            #[cfg(target_os = "windows")]
+            handle_types: vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_WIN32,
+            #[cfg(target_os = "linux")]
+            handle_types: vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD,
+            ..Default::default()
+        };
+        let create_info = vk::SemaphoreCreateInfo {
+            p_next: &export_info as *const _ as _,
+            ..Default::default()
+        };
+
+        unsafe { self.device.create_semaphore(&create_info, None) }
+    }
+
+    pub fn get_external_semaphore_handle(
+        &self,
+        semaphore: vk::Semaphore,
+    ) -> VkResult<InteropHandle> {
+        #[cfg(target_os = "windows")]
+        unsafe {
+            let mut handle = std::ptr::null_mut();
+
+            let win32_handle_info = vk::SemaphoreGetWin32HandleInfoKHR::builder()
+                .semaphore(semaphore)
+                .handle_type(vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_WIN32)
+                .build();
+
+            (self.khr_external_semaphore.get_semaphore_win32_handle_khr)(
+                self.device.handle(),
+                &win32_handle_info,
+                &mut handle,
+            )
+            .result()?;
+            Ok(handle)
+        }
+
+        #[cfg(target_os = "linux")]
+        unsafe {
+            let mut handle = 0;
+
+            let handle_info = vk::SemaphoreGetFdInfoKHR::builder()
+                .semaphore(semaphore)
+                .handle_type(vk::ExternalSemaphoreHandleTypeFlags::OPAQUE_FD)
+                .build();
+
+            (self.khr_external_semaphore.get_semaphore_fd_khr)(
+                self.device.handle(),
+                &handle_info,
+                &mut handle,
+            )
+            .result()?;
+
+            Ok(handle)
+        }
+    }
+}
+
+impl ImageLayout {
+    pub fn to_vk(&self) -> vk::ImageLayout {
+        match self {
+            ImageLayout::General => vk::ImageLayout::GENERAL,
+            ImageLayout::ShaderReadOnly => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageLayout::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        }
+    }
 }
 
 impl ImageFormat {
@@ -214,4 +343,14 @@ impl ImageFormat {
     pub fn from_vk(gl_format: vk::Format) -> Option<Self> {
         VK_FORMATS.get_by_right(&gl_format).copied()
     }
+
+    pub fn vk_aspects(&self) -> vk::ImageAspectFlags {
+        if self.has_stencil() {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else if self.is_depth() {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        }
+    }
 }
@@ -5,11 +5,46 @@ pub struct ImageCreateInfo {
     pub width: u32,
     pub height: u32,
     pub layers: u32,
+    /// 6 for cubemaps, every array layer then holds a full set of faces
+    pub faces: u32,
     pub mip_count: u32,
     pub sample_count: u32,
     pub format: ImageFormat,
+    pub usage: ImageUsage,
     // pub dimension: 2D
-    // pub usage: T_SRC T_DST ATTACHMENT SAMPLER
+}
+
+bitflags::bitflags! {
+    /// How an image is going to be used, by both the application and the layer
+    pub struct ImageUsage: u32 {
+        const COLOR_ATTACHMENT         = 0b00000001;
+        const DEPTH_STENCIL_ATTACHMENT = 0b00000010;
+        const STORAGE                  = 0b00000100;
+        const TRANSFER_SRC             = 0b00001000;
+        const TRANSFER_DST             = 0b00010000;
+        const SAMPLED                  = 0b00100000;
+        /// Views of the image may use a different format of the same size
+        const MUTABLE_FORMAT           = 0b01000000;
+        const INPUT_ATTACHMENT         = 0b10000000;
+    }
+}
+
+impl ImageCreateInfo {
+    /// The number of layers the underlying image has, counting each cubemap face
+    ///
+    /// Saturates instead of overflowing, callers validate the layer count against the device's
+    /// limits anyway.
+    pub fn array_layers(&self) -> u32 {
+        self.layers.saturating_mul(self.faces)
+    }
+}
+
+/// The layout a shared image is handed over in, both APIs have to agree on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageLayout {
+    General,
+    ShaderReadOnly,
+    TransferSrc,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -17,11 +52,15 @@ pub enum ImageFormat {
     // Normal 32 bit formats
     Rgba8Unorm,
     Rgba8UnormSrgb,
+    Bgra8Unorm,
+    Bgra8UnormSrgb,
 
     // Packed 32 bit formats
     Rgb10a2Unorm,
+    Rg11b10Float,
 
     // Normal 64 bit formats
+    Rgba16Unorm,
     Rgba16Float,
 
     // Normal 128 bit formats
@@ -29,11 +68,264 @@ pub enum ImageFormat {
 
     // Depth-stencil formats
     Depth32Float,
+    Depth32FloatStencil8,
+    Depth24Plus,
     Depth24PlusStencil8,
 
     // Non-WGPU Depth-stencil formats
     // Depth24FloatPlusStencil8Uint,
     Depth16Unorm,
+
+    // Non-WGPU packed 32 bit formats
+    Bgr10a2Unorm,
+}
+
+/// What a format stores and how
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatInfo {
+    pub color: bool,
+    pub depth: bool,
+    pub stencil: bool,
+    /// Values are stored as floats, so color may go beyond 0 to 1
+    pub float: bool,
+    /// Bits of the widest color or depth channel
+    pub bits_per_channel: u32,
+    pub bits_per_texel: u32,
+    /// Color values are sRGB encoded, alpha never is
+    pub srgb: bool,
+    /// The format with the same texel layout but the opposite transfer function
+    pub srgb_pair: Option<ImageFormat>,
+}
+
+impl FormatInfo {
+    const fn color(bits_per_channel: u32, bits_per_texel: u32) -> Self {
+        Self {
+            color: true,
+            depth: false,
+            stencil: false,
+            float: false,
+            bits_per_channel,
+            bits_per_texel,
+            srgb: false,
+            srgb_pair: None,
+        }
+    }
+
+    const fn depth(bits_per_channel: u32, bits_per_texel: u32, stencil: bool) -> Self {
+        Self {
+            color: false,
+            depth: true,
+            stencil,
+            float: false,
+            bits_per_channel,
+            bits_per_texel,
+            srgb: false,
+            srgb_pair: None,
+        }
+    }
+
+    const fn float(self) -> Self {
+        Self {
+            float: true,
+            ..self
+        }
+    }
+
+    const fn srgb_pair(self, srgb: bool, srgb_pair: ImageFormat) -> Self {
+        Self {
+            srgb,
+            srgb_pair: Some(srgb_pair),
+            ..self
+        }
+    }
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 15] = [
+        ImageFormat::Rgba8Unorm,
+        ImageFormat::Rgba8UnormSrgb,
+        ImageFormat::Bgra8Unorm,
+        ImageFormat::Bgra8UnormSrgb,
+        ImageFormat::Rgb10a2Unorm,
+        ImageFormat::Rg11b10Float,
+        ImageFormat::Rgba16Unorm,
+        ImageFormat::Rgba16Float,
+        ImageFormat::Rgba32Float,
+        ImageFormat::Depth32Float,
+        ImageFormat::Depth32FloatStencil8,
+        ImageFormat::Depth24Plus,
+        ImageFormat::Depth24PlusStencil8,
+        ImageFormat::Depth16Unorm,
+        ImageFormat::Bgr10a2Unorm,
+    ];
+
+    pub const fn info(&self) -> FormatInfo {
+        use ImageFormat::*;
+        match self {
+            Rgba8Unorm => FormatInfo::color(8, 32).srgb_pair(false, Rgba8UnormSrgb),
+            Rgba8UnormSrgb => FormatInfo::color(8, 32).srgb_pair(true, Rgba8Unorm),
+            Bgra8Unorm => FormatInfo::color(8, 32).srgb_pair(false, Bgra8UnormSrgb),
+            Bgra8UnormSrgb => FormatInfo::color(8, 32).srgb_pair(true, Bgra8Unorm),
+            Rgb10a2Unorm | Bgr10a2Unorm => FormatInfo::color(10, 32),
+            Rg11b10Float => FormatInfo::color(11, 32).float(),
+            Rgba16Unorm => FormatInfo::color(16, 64),
+            Rgba16Float => FormatInfo::color(16, 64).float(),
+            Rgba32Float => FormatInfo::color(32, 128).float(),
+            Depth32Float => FormatInfo::depth(32, 32, false).float(),
+            //Drivers store the stencil separately or pad it to 64 bits
+            Depth32FloatStencil8 => FormatInfo::depth(32, 64, true).float(),
+            //Drivers may pad 24 bit depth to 32 bits
+            Depth24Plus => FormatInfo::depth(24, 32, false),
+            Depth24PlusStencil8 => FormatInfo::depth(24, 32, true),
+            Depth16Unorm => FormatInfo::depth(16, 16, false),
+        }
+    }
+
+    pub fn is_depth(&self) -> bool {
+        self.info().depth
+    }
+
+    pub fn has_stencil(&self) -> bool {
+        self.info().stencil
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        self.info().srgb
+    }
+
+    /// The format with the same texel layout whose values are read and written as is
+    pub fn linear(&self) -> Self {
+        match self.info() {
+            FormatInfo {
+                srgb: true,
+                srgb_pair: Some(pair),
+                ..
+            } => pair,
+            _ => *self,
+        }
+    }
+
+    /// The format with the same texel layout but the opposite transfer function
+    pub fn srgb_counterpart(&self) -> Option<Self> {
+        self.info().srgb_pair
+    }
+
+    /// The format with the same channels stored in a different order
+    pub fn reordered(&self) -> Option<Self> {
+        use ImageFormat::*;
+        match self {
+            Rgba8Unorm => Some(Bgra8Unorm),
+            Rgba8UnormSrgb => Some(Bgra8UnormSrgb),
+            Bgra8Unorm => Some(Rgba8Unorm),
+            Bgra8UnormSrgb => Some(Rgba8UnormSrgb),
+            Rgb10a2Unorm => Some(Bgr10a2Unorm),
+            Bgr10a2Unorm => Some(Rgb10a2Unorm),
+            _ => None,
+        }
+    }
+
+    /// Formats an image of this format can be copied into, best first
+    ///
+    /// Channel order is handled by rendering and blitting, which read and write channels by name.
+    /// Float formats end with the formats they can be tone mapped into.
+    pub fn equivalents(&self) -> Vec<ImageFormat> {
+        use ImageFormat::*;
+        let reordered = self.reordered();
+        let mut equivalents = [
+            Some(*self),
+            reordered,
+            self.srgb_counterpart(),
+            reordered.and_then(|format| format.srgb_counterpart()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        let info = self.info();
+        if info.color && info.float {
+            //Wider floats hold every value, everything after that needs tone mapping
+            equivalents.extend(
+                [Rgba16Float, Rgba32Float]
+                    .into_iter()
+                    .filter(|format| format.info().bits_per_channel > info.bits_per_channel),
+            );
+            equivalents.extend([
+                Rgba16Unorm,
+                Rgb10a2Unorm,
+                Bgr10a2Unorm,
+                Rgba8UnormSrgb,
+                Bgra8UnormSrgb,
+                Rgba8Unorm,
+                Bgra8Unorm,
+            ]);
+        }
+        equivalents
+    }
+
+    /// Whether copying into `target` has to bring color into its 0 to 1 range
+    pub fn needs_tone_mapping(&self, target: ImageFormat) -> bool {
+        self.info().color && self.info().float && !target.info().float
+    }
+}
+
+/// The formats an image is sampled and rendered through when copying it into an image of another format
+///
+/// Matching transfer functions copy the stored bits unchanged, sRGB images are viewed as linear so they
+/// are not decoded and encoded again on the way. Otherwise the views keep their own formats and the
+/// hardware decodes sRGB when sampling and encodes it when rendering. Tone mapping encodes sRGB
+/// itself so it always renders through a linear view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewFormats {
+    pub sampled: ImageFormat,
+    pub rendered: ImageFormat,
+    /// Whether a view differs from its image's format, which has to be allowed when creating them
+    pub mutable: bool,
+}
+
+impl ViewFormats {
+    pub fn new(src: ImageFormat, dst: ImageFormat) -> Self {
+        let (sampled, rendered) = if src.needs_tone_mapping(dst) {
+            (src, dst.linear())
+        } else if src.is_srgb() == dst.is_srgb() {
+            (src.linear(), dst.linear())
+        } else {
+            (src, dst)
+        };
+        Self {
+            sampled,
+            rendered,
+            mutable: sampled != src || rendered != dst,
+        }
+    }
+
+    /// The views used when the copy changes colors, sRGB is always decoded when sampling
+    ///
+    /// Every view is either one `new` picks or the image's own format, so images created for
+    /// the views of `new` can be viewed like this too.
+    pub fn converted(src: ImageFormat, dst: ImageFormat) -> Self {
+        if src.needs_tone_mapping(dst) {
+            Self::new(src, dst)
+        } else {
+            Self {
+                sampled: src,
+                rendered: dst,
+                mutable: false,
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for ImageFormat {
+    type Err = String;
+
+    /// Parses a format from its variant name, ignoring case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|format| format!("{:?}", format).eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| format!("unknown image format `{}`", s))
+    }
 }
 
 #[cfg(target_os = "windows")]
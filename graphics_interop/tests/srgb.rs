@@ -0,0 +1,157 @@
+//! Models what the copy's views do to every 8 bit value, the views are chosen by `ViewFormats`
+//! while sampling and rendering follow the sRGB and UNORM conversion rules of OpenGL and Vulkan.
+
+use graphics_interop::{ImageFormat, ViewFormats};
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Reads a texel through a view, alpha is never converted
+fn sample(view: ImageFormat, texel: [u8; 4]) -> [f32; 4] {
+    let mut out = texel.map(|value| value as f32 / 255.0);
+    if view.is_srgb() {
+        for value in &mut out[..3] {
+            *value = srgb_to_linear(*value);
+        }
+    }
+    out
+}
+
+/// Writes a texel through a view
+fn render(view: ImageFormat, mut color: [f32; 4]) -> [u8; 4] {
+    if view.is_srgb() {
+        for value in &mut color[..3] {
+            *value = linear_to_srgb(*value);
+        }
+    }
+    color.map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn copy(src: ImageFormat, dst: ImageFormat, texel: [u8; 4]) -> [u8; 4] {
+    let views = ViewFormats::new(src, dst);
+    render(views.rendered, sample(views.sampled, texel))
+}
+
+fn every_value() -> impl Iterator<Item = [u8; 4]> {
+    (0..=255u8).map(|value| [value, value, value, 255 - value])
+}
+
+#[test]
+fn matching_formats_copy_bits_unchanged() {
+    for format in [ImageFormat::Rgba8Unorm, ImageFormat::Rgba8UnormSrgb] {
+        //Rendering into a BGRA view stores every channel where it belongs
+        for dst in [format, format.reordered().unwrap()] {
+            for texel in every_value() {
+                assert_eq!(
+                    copy(format, dst, texel),
+                    texel,
+                    "{:?} into {:?}",
+                    format,
+                    dst
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn srgb_into_linear_decodes() {
+    let decode = |texel| copy(ImageFormat::Rgba8UnormSrgb, ImageFormat::Rgba8Unorm, texel);
+    assert_eq!(decode([0, 128, 188, 255]), [0, 55, 128, 255]);
+    assert_eq!(decode([1, 64, 255, 77]), [0, 13, 255, 77]);
+}
+
+#[test]
+fn linear_into_srgb_encodes() {
+    let encode = |texel| copy(ImageFormat::Rgba8Unorm, ImageFormat::Rgba8UnormSrgb, texel);
+    assert_eq!(encode([0, 55, 128, 255]), [0, 128, 188, 255]);
+    assert_eq!(encode([1, 13, 255, 77]), [13, 64, 255, 77]);
+}
+
+/// Blits between two sRGB images decode and encode every value, which has to be lossless
+#[test]
+fn srgb_round_trips() {
+    for value in 0..=255u8 {
+        let color = sample(ImageFormat::Rgba8UnormSrgb, [value; 4]);
+        assert_eq!(render(ImageFormat::Rgba8UnormSrgb, color)[0], value);
+    }
+}
+
+#[test]
+fn only_srgb_copies_need_mutable_formats() {
+    use ImageFormat::*;
+    let views = |src, dst| {
+        let views = ViewFormats::new(src, dst);
+        (views.sampled, views.rendered, views.mutable)
+    };
+    assert_eq!(
+        views(Rgba8UnormSrgb, Rgba8UnormSrgb),
+        (Rgba8Unorm, Rgba8Unorm, true)
+    );
+    assert_eq!(
+        views(Rgba8UnormSrgb, Rgba8Unorm),
+        (Rgba8UnormSrgb, Rgba8Unorm, false)
+    );
+    assert_eq!(
+        views(Rgba8Unorm, Rgba8UnormSrgb),
+        (Rgba8Unorm, Rgba8UnormSrgb, false)
+    );
+    for format in ImageFormat::ALL
+        .into_iter()
+        .filter(|format| !format.is_srgb())
+    {
+        assert_eq!(views(format, format), (format, format, false));
+    }
+
+    //Converting colors decodes and encodes sRGB instead
+    let converted = ViewFormats::converted(Rgba8UnormSrgb, Rgba8UnormSrgb);
+    assert_eq!(
+        (converted.sampled, converted.rendered, converted.mutable),
+        (Rgba8UnormSrgb, Rgba8UnormSrgb, false)
+    );
+    assert_eq!(
+        ViewFormats::converted(Rgba16Float, Rgba8UnormSrgb),
+        ViewFormats::new(Rgba16Float, Rgba8UnormSrgb)
+    );
+}
+
+#[test]
+fn counterparts_share_texel_layout() {
+    for format in ImageFormat::ALL {
+        if let Some(counterpart) = format.srgb_counterpart() {
+            assert_ne!(format.is_srgb(), counterpart.is_srgb());
+            assert_eq!(format.linear(), counterpart.linear());
+            assert_eq!(counterpart.srgb_counterpart(), Some(format));
+        }
+    }
+}
+
+#[test]
+fn equivalents_prefer_exact_copies() {
+    use ImageFormat::*;
+    assert_eq!(
+        Rgba8UnormSrgb.equivalents(),
+        [Rgba8UnormSrgb, Bgra8UnormSrgb, Rgba8Unorm, Bgra8Unorm]
+    );
+    assert_eq!(Rgb10a2Unorm.equivalents(), [Rgb10a2Unorm, Bgr10a2Unorm]);
+    assert_eq!(
+        Rgba16Float.equivalents()[..3],
+        [Rgba16Float, Rgba32Float, Rgba16Unorm]
+    );
+    assert!(!Rgba16Float.needs_tone_mapping(Rgba32Float));
+    assert!(Rgba16Float.needs_tone_mapping(Rgba8UnormSrgb));
+    assert!(!Depth32Float.needs_tone_mapping(Depth16Unorm));
+}
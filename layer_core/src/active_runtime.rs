@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use openxr::sys::{self as xr, pfn};
+
+use crate::loader_interfaces::*;
+
+/// The active runtime, loaded and negotiated with the way the loader does it
+///
+/// Calls made before xrCreateInstance don't come with the next layer's functions, so with
+/// `instance.load_active_runtime` those go straight to the runtime instead, skipping any layers
+/// bellow this one.
+struct ActiveRuntime {
+    _library: libloading::Library,
+    get_instance_proc_addr: pfn::GetInstanceProcAddr,
+}
+
+lazy_static! {
+    static ref ACTIVE_RUNTIME: Option<ActiveRuntime> = unsafe { load() };
+}
+
+/// The runtime's xrGetInstanceProcAddr, `None` if no runtime could be loaded
+pub fn get_instance_proc_addr() -> Option<pfn::GetInstanceProcAddr> {
+    ACTIVE_RUNTIME
+        .as_ref()
+        .map(|runtime| runtime.get_instance_proc_addr)
+}
+
+unsafe fn load() -> Option<ActiveRuntime> {
+    let manifest_path = match manifest_paths().into_iter().find(|path| path.is_file()) {
+        Some(path) => path,
+        None => {
+            warn!("No active runtime manifest found");
+            return None;
+        }
+    };
+    let library_path = match read_library_path(&manifest_path) {
+        Ok(path) => path,
+        Err(err) => {
+            warn!(
+                "Failed to read runtime manifest {}: {}",
+                manifest_path.display(),
+                err
+            );
+            return None;
+        }
+    };
+
+    let library = match libloading::Library::new(&library_path) {
+        Ok(library) => library,
+        Err(err) => {
+            warn!("Failed to load runtime {}: {}", library_path.display(), err);
+            return None;
+        }
+    };
+    let negotiate = *library
+        .get::<FnNegotiateLoaderRuntimeInterface>(b"xrNegotiateLoaderRuntimeInterface\0")
+        .ok()?;
+
+    let loader_info = XrNegotiateLoaderInfo {
+        ty: xr::StructureType::from_raw(XR_LOADER_INTERFACE_STRUCT_LOADER_INFO),
+        struct_version: XR_LOADER_INFO_STRUCT_VERSION,
+        struct_size: std::mem::size_of::<XrNegotiateLoaderInfo>(),
+        min_interface_version: 1,
+        max_interface_version: XR_CURRENT_LOADER_RUNTIME_VERSION,
+        min_api_version: xr::Version::new(1, 0, 0),
+        max_api_version: xr::CURRENT_API_VERSION,
+    };
+    let mut request = XrNegotiateRuntimeRequest {
+        ty: xr::StructureType::from_raw(XR_LOADER_INTERFACE_STRUCT_RUNTIME_REQUEST),
+        struct_version: XR_RUNTIME_INFO_STRUCT_VERSION,
+        struct_size: std::mem::size_of::<XrNegotiateRuntimeRequest>(),
+        runtime_interface_version: 0,
+        runtime_api_version: xr::Version::new(0, 0, 0),
+        get_instance_proc_addr: None,
+    };
+    let result = negotiate(&loader_info, &mut request);
+    match request.get_instance_proc_addr {
+        Some(get_instance_proc_addr) if result == xr::Result::SUCCESS => {
+            debug!("Negotiated with runtime {}", library_path.display());
+            Some(ActiveRuntime {
+                _library: library,
+                get_instance_proc_addr,
+            })
+        }
+        _ => {
+            warn!(
+                "Runtime {} failed negotiation: {}",
+                library_path.display(),
+                result
+            );
+            None
+        }
+    }
+}
+
+/// Where the loader looks for the active runtime manifest, in order
+fn manifest_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(path) = std::env::var_os("XR_RUNTIME_JSON") {
+        paths.push(PathBuf::from(path));
+    }
+    //The Windows loader reads the registry, only the override is supported there
+    #[cfg(target_os = "linux")]
+    {
+        let manifest = Path::new("openxr/1/active_runtime.json");
+        if let Some(config_dir) = dirs::config_dir() {
+            paths.push(config_dir.join(manifest));
+        }
+        let config_dirs =
+            std::env::var("XDG_CONFIG_DIRS").unwrap_or_else(|_| "/etc/xdg".to_string());
+        paths.extend(
+            config_dirs
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| Path::new(dir).join(manifest)),
+        );
+        paths.push(Path::new("/etc").join(manifest));
+    }
+    paths
+}
+
+/// The runtime library named by a manifest, relative paths are relative to the manifest
+fn read_library_path(manifest_path: &Path) -> Result<PathBuf, String> {
+    let manifest = std::fs::read_to_string(manifest_path).map_err(|err| err.to_string())?;
+    let manifest =
+        serde_json::from_str::<serde_json::Value>(&manifest).map_err(|err| err.to_string())?;
+    let library_path = manifest["runtime"]["library_path"]
+        .as_str()
+        .ok_or_else(|| "missing runtime.library_path".to_string())?;
+
+    let library_path = Path::new(library_path);
+    //Bare file names are left to the system's library search
+    Ok(
+        if library_path.is_relative() && library_path.components().count() > 1 {
+            manifest_path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(library_path)
+        } else {
+            library_path.to_path_buf()
+        },
+    )
+}
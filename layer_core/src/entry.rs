@@ -3,11 +3,13 @@ use std::sync::atomic::AtomicBool;
 use std::{ffi::CStr, sync::Arc};
 
 use crate::loader_interfaces::*;
+use crate::quirks::{RuntimeQuirks, RuntimeVersion};
+use crate::settings::{OpenGLMode, Settings};
 use crate::wrappers::instance::{InnerInstance, InstanceWrapper, Runtime};
 use crate::wrappers::XrHandle;
 use crate::ToResult;
 
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 
 use openxr::sys as xr;
 use openxr::{ExtensionSet, InstanceExtensions, Result};
@@ -47,6 +49,10 @@ fn create_instance(
 
     debug!("Initializing OpenXR Entry");
 
+    if let Ok(mut next) = crate::interceptors::NEXT_GET_INSTANCE_PROC_ADDR.write() {
+        *next = Some(next_info.next_get_instance_proc_addr);
+    }
+
     //Setup the OpenXR wrapper for the layer bellow us
     let entry = unsafe {
         openxr::Entry::from_get_instance_proc_addr(next_info.next_get_instance_proc_addr)?
@@ -54,12 +60,37 @@ fn create_instance(
 
     let available_extensions = entry.enumerate_extensions()?;
 
-    //TODO set this to true depending on env var
-    let disable_opengl = true;
+    let (application_name, engine_name) = unsafe {
+        (
+            CStr::from_ptr(instance_info.application_info.application_name.as_ptr())
+                .to_string_lossy(),
+            CStr::from_ptr(instance_info.application_info.engine_name.as_ptr()).to_string_lossy(),
+        )
+    };
+
+    let mut settings = Settings::load(&layer_info.settings_file_location);
+    crate::logging::reconfigure(&settings.log_config());
+
+    let profile = settings
+        .find_profile(&application_name, &engine_name)
+        .cloned();
+    if let Some(profile) = &profile {
+        settings.apply_profile(profile);
+    }
+    if settings.swapchain.upscale != 1.0 && settings.swapchain.upscale().is_none() {
+        warn!(
+            "Ignoring `upscale = {}`, it has to be between 1 and 4",
+            settings.swapchain.upscale
+        );
+    }
+
+    let mut native_opengl = false;
+    let mut opengl_enabled = false;
+    let mut supported_extensions = ExtensionSet::default();
 
     //Initialize the layer bellow us
     let result = unsafe {
-        let mut needs_opengl_replacement = false;
+        let mut needs_vulkan = false;
 
         let mut extensions = std::slice::from_raw_parts(
             instance_info.enabled_extension_names,
@@ -69,21 +100,45 @@ fn create_instance(
         .filter_map(|ext| {
             let ext_name = CStr::from_ptr(*ext).to_str().unwrap();
             if ext_name == "XR_KHR_opengl_enable" {
-                if disable_opengl {
-                    needs_opengl_replacement = true;
-                } else if !available_extensions.khr_opengl_enable {
-                    needs_opengl_replacement = true;
+                opengl_enabled = true;
+                if settings.opengl.mode != OpenGLMode::Passthrough
+                    || !available_extensions.khr_opengl_enable
+                {
+                    needs_vulkan = true;
+                }
+                if !available_extensions.khr_opengl_enable {
                     return None;
                 }
+                native_opengl = true;
             }
             Some(*ext)
         })
         .collect::<Vec<_>>();
 
-        if needs_opengl_replacement {
-            extensions.push("XR_KHR_vulkan_enable2\0".as_ptr() as *const i8);
+        let enabled = |extensions: &[*const i8], name: &[u8]| {
+            extensions
+                .iter()
+                .any(|ext| CStr::from_ptr(*ext).to_bytes() == name)
+        };
+
+        if needs_vulkan
+            && !enabled(&extensions, b"XR_KHR_vulkan_enable2")
+            && !enabled(&extensions, b"XR_KHR_vulkan_enable")
+        {
+            //Prefer XR_KHR_vulkan_enable2, older runtimes only have the original extension
+            if available_extensions.khr_vulkan_enable2 {
+                extensions.push("XR_KHR_vulkan_enable2\0".as_ptr() as *const i8);
+            } else if available_extensions.khr_vulkan_enable {
+                info!("Runtime lacks XR_KHR_vulkan_enable2, falling back to XR_KHR_vulkan_enable");
+                extensions.push("XR_KHR_vulkan_enable\0".as_ptr() as *const i8);
+            } else {
+                warn!("Runtime supports no Vulkan extension, OpenGL sessions will fail");
+            }
         }
 
+        supported_extensions.khr_vulkan_enable2 = enabled(&extensions, b"XR_KHR_vulkan_enable2");
+        supported_extensions.khr_vulkan_enable = enabled(&extensions, b"XR_KHR_vulkan_enable");
+
         let mut instance_info2 = *instance_info;
         instance_info2.enabled_extension_names = extensions.as_ptr();
         instance_info2.enabled_extension_count = extensions.len() as u32;
@@ -94,8 +149,7 @@ fn create_instance(
         (next_info.next_create_api_layer_instance)(&instance_info2, &layer_info2, instance).result()
     }?;
 
-    let mut supported_extensions = ExtensionSet::default();
-    supported_extensions.khr_vulkan_enable2 = true;
+    supported_extensions.khr_opengl_enable = native_opengl;
 
     let inner = unsafe {
         InnerInstance {
@@ -105,16 +159,27 @@ fn create_instance(
         }
     };
 
-    let runtime_name = unsafe {
+    let (runtime_name, runtime_version) = unsafe {
         let mut instance_properties = xr::InstanceProperties::out(std::ptr::null_mut());
         (inner.core.get_instance_properties)(*instance, instance_properties.as_mut_ptr())
             .result()?;
         let instance_properties = instance_properties.assume_init();
 
-        CStr::from_ptr(std::mem::transmute(
+        let runtime_name = CStr::from_ptr(std::mem::transmute(
             instance_properties.runtime_name.as_ptr(),
         ))
         .to_string_lossy()
+        .into_owned();
+        let version = instance_properties.runtime_version;
+
+        (
+            runtime_name,
+            RuntimeVersion {
+                major: version.major(),
+                minor: version.minor(),
+                patch: version.patch(),
+            },
+        )
     };
 
     let runtime = match runtime_name.deref() {
@@ -125,19 +190,37 @@ fn create_instance(
         _ => Runtime::Other(runtime_name.to_string()),
     };
 
+    let quirks = RuntimeQuirks::load(&runtime, runtime_version, &settings.runtimes);
+    debug!(
+        "Runtime `{}` {} quirks: {:?}",
+        runtime_name, runtime_version, quirks
+    );
+
     let wrapper = InstanceWrapper {
         handle: *instance,
         inner: Arc::new(inner),
         systems: Default::default(),
         sessions: Default::default(),
         runtime,
+        runtime_version,
+        quirks,
+        settings,
+        profile,
+        native_opengl,
+        opengl_enabled,
     };
 
-    xr::Instance::all_wrappers().insert(*instance, Arc::new(wrapper));
+    info!(
+        "Instance created for application `{}` engine `{}` with profile `{}`",
+        application_name,
+        engine_name,
+        wrapper
+            .profile
+            .as_ref()
+            .map_or("none", |profile| profile.label())
+    );
 
-    info!("Instance created with name 2 2 2 2  222`{}`", unsafe {
-        CStr::from_ptr(&instance_info.application_info.application_name as _).to_string_lossy()
-    });
+    xr::Instance::all_wrappers().insert(*instance, Arc::new(wrapper));
 
     Ok(result)
 }
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
-use graphics_interop::apis::opengl::OpenGLInterop;
+use graphics_interop::{apis::opengl::OpenGLInterop, ImageLayout};
+use log::{error, warn};
 use openxr::sys as xr;
 
 use crate::wrappers::swapchain::SwapchainFrontend;
@@ -27,32 +28,78 @@ pub struct SwapchainFrontendOpenGL {
     opengl: Arc<OpenGLFrontend>,
     memory_objects: Vec<u32>,
     images: Vec<u32>,
+    /// Signalled on release instead of waiting with glFinish, empty if they aren't supported
+    semaphores: Vec<u32>,
+    /// The layout the backend expects the images in
+    layout: ImageLayout,
 }
 
 impl SwapchainFrontendOpenGL {
+    /// Imports the backend's images, if the semaphores can't be imported releases wait with
+    /// glFinish instead and `uses_semaphores` is false
     pub fn load(
         handles: &[(graphics_interop::InteropHandle, u64)],
+        semaphore_handles: &[graphics_interop::InteropHandle],
         opengl: Arc<OpenGLFrontend>,
         image_info: &graphics_interop::ImageCreateInfo,
-    ) -> Self {
-        let mut memory_objects = Vec::with_capacity(handles.len());
-        let mut images = Vec::with_capacity(handles.len());
+        layout: ImageLayout,
+    ) -> openxr::Result<Self> {
+        let mut frontend = Self {
+            opengl,
+            memory_objects: Vec::with_capacity(handles.len()),
+            images: Vec::with_capacity(handles.len()),
+            semaphores: Vec::with_capacity(semaphore_handles.len()),
+            layout,
+        };
+        let interop = &frontend.opengl.interop;
 
         for &(handle, size) in handles {
-            memory_objects.push(opengl.interop.import_memory(handle, size).unwrap());
-            images.push(
-                opengl
-                    .interop
-                    .import_image(image_info, *memory_objects.last().unwrap(), 0)
-                    .unwrap(),
-            );
+            let imported = interop
+                .import_memory(handle, size)
+                .and_then(|memory_object| {
+                    frontend.memory_objects.push(memory_object);
+                    interop.import_image(image_info, memory_object, 0)
+                });
+            match imported {
+                Ok(image) => frontend.images.push(image),
+                Err(error) => {
+                    error!(
+                        "Failed to import a swapchain image into OpenGL: {:#X}",
+                        error
+                    );
+                    //Whatever was imported before the failure is deleted again
+                    frontend.destroy();
+                    return Err(xr::Result::ERROR_RUNTIME_FAILURE);
+                }
+            }
         }
 
-        Self {
-            opengl,
-            memory_objects,
-            images,
+        for &handle in semaphore_handles {
+            match interop.import_semaphore(handle) {
+                Ok(semaphore) => frontend.semaphores.push(semaphore),
+                Err(error) => {
+                    warn!(
+                        "Failed to import a semaphore into OpenGL, using glFinish: {:#X}",
+                        error
+                    );
+                    unsafe {
+                        interop.gl.DeleteSemaphoresEXT(
+                            frontend.semaphores.len() as i32,
+                            frontend.semaphores.as_ptr(),
+                        )
+                    };
+                    frontend.semaphores.clear();
+                    break;
+                }
+            }
         }
+
+        Ok(frontend)
+    }
+
+    /// Whether releases signal a semaphore for the backend to wait on
+    pub fn uses_semaphores(&self) -> bool {
+        !self.semaphores.is_empty()
     }
 }
 
@@ -86,17 +133,21 @@ impl SwapchainFrontend for SwapchainFrontendOpenGL {
         Ok(xr::Result::SUCCESS)
     }
 
-    fn release_image(&self, _: u32) {
+    fn release_image(&self, index: u32) {
         self.opengl.context.make_current();
-        unsafe {
+        match self.semaphores.get(index as usize) {
+            Some(&semaphore) => self.opengl.interop.signal_semaphore(
+                semaphore,
+                self.images[index as usize],
+                self.layout,
+            ),
             //We need to wait for all OpenGL calls to finish execution before copying the image
-            self.opengl.interop.gl.Finish();
-
-            //TODO use fences on android
+            None => unsafe { self.opengl.interop.gl.Finish() },
         }
     }
 
     fn destroy(&self) {
+        self.opengl.context.make_current();
         unsafe {
             self.opengl
                 .interop
@@ -105,7 +156,13 @@ impl SwapchainFrontend for SwapchainFrontendOpenGL {
             self.opengl.interop.gl.DeleteMemoryObjectsEXT(
                 self.memory_objects.len() as i32,
                 self.memory_objects.as_ptr(),
-            )
+            );
+            if !self.semaphores.is_empty() {
+                self.opengl
+                    .interop
+                    .gl
+                    .DeleteSemaphoresEXT(self.semaphores.len() as i32, self.semaphores.as_ptr());
+            }
         }
     }
 }
@@ -1,5 +1,12 @@
 use core::slice;
-use std::{borrow::Cow, ffi::CStr, io::Cursor, os::raw::c_char};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::{CStr, CString},
+    io::Cursor,
+    os::raw::c_char,
+    sync::Mutex,
+};
 
 use ash::{
     extensions::ext::DebugUtils,
@@ -8,7 +15,7 @@ use ash::{
     Device, Entry, Instance,
 };
 use graphics_interop::apis::vulkan::VulkanInterop;
-use log::error;
+use log::{error, warn};
 use openxr::sys as xr;
 
 use crate::{wrappers::instance::InstanceWrapper, ToResult};
@@ -27,9 +34,72 @@ pub struct VkBackend {
 
     pub command_pool: vk::CommandPool,
     pub nearest_sampler: vk::Sampler,
+    /// Used instead of `nearest_sampler` when images are scaled up
+    pub linear_sampler: vk::Sampler,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
+    /// A sampled image at binding 0 read with the sampler at binding 1
+    pub convert_descriptor_set_layout: vk::DescriptorSetLayout,
 
     pub interop: VulkanInterop,
+
+    /// Copies waiting for `submit_queued_copies`
+    pub queued_copies: Mutex<Vec<QueuedCopy>>,
+    /// Draw pipelines shared by every swapchain, see `pipeline`
+    pipelines: Mutex<HashMap<PipelineKey, DrawPipeline>>,
+}
+
+/// What a draw pipeline depends on, the image size is set when recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    format: vk::Format,
+    sample_count: vk::SampleCountFlags,
+    /// Whether the conversion shader is used instead of the plain copy
+    convert: bool,
+}
+
+/// A pipeline for the fullscreen draw, owned by the `VkBackend` it came from
+#[derive(Debug, Clone, Copy)]
+pub struct DrawPipeline {
+    pub layout: vk::PipelineLayout,
+    pub render_pass: vk::RenderPass,
+    pub pipeline: vk::Pipeline,
+}
+
+impl DrawPipeline {
+    /// Null handles are skipped, so a partially created pipeline can be destroyed too
+    unsafe fn destroy(&self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.layout, None);
+        device.destroy_render_pass(self.render_pass, None);
+    }
+}
+
+/// The parts of a swapchain image's release that go into a batched submission
+#[derive(Debug, Clone, Copy)]
+pub struct QueuedCopy {
+    /// Signaled by OpenGL once the application is done with the image
+    pub wait_semaphore: Option<vk::Semaphore>,
+    pub command_buffer: vk::CommandBuffer,
+    pub fence: vk::Fence,
+}
+
+/// Push constants of the conversion shader, which tone maps and converts alpha
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ConvertConstants {
+    /// 0 none, 1 clamp, 2 reinhard, 3 aces
+    pub curve: u32,
+    pub encode_srgb: u32,
+    /// One step of the target format, 0 disables dithering
+    pub dither_scale: f32,
+    /// The bits of an `AlphaConversion`
+    pub alpha: u32,
+}
+
+impl ConvertConstants {
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts((self as *const Self).cast(), std::mem::size_of::<Self>()) }
+    }
 }
 
 impl VkBackend {
@@ -39,9 +109,10 @@ impl VkBackend {
     ) -> Result<VkBackend, ()> {
         let entry = Entry::load().unwrap();
         let exts = &xr_instance.inner.exts;
+        let validation = xr_instance.settings.vulkan.validation;
         let xr_instance = xr_instance.handle;
 
-        let _requirements = if let Some(vulkan) = exts.khr_vulkan_enable2 {
+        let requirements = if let Some(vulkan) = exts.khr_vulkan_enable2 {
             let mut reqs =
                 xr::GraphicsRequirementsVulkanKHR::out(std::ptr::null_mut()).assume_init();
             let result =
@@ -51,36 +122,90 @@ impl VkBackend {
                 return Err(());
             }
             reqs
+        } else if let Some(vulkan) = exts.khr_vulkan_enable {
+            let mut reqs =
+                xr::GraphicsRequirementsVulkanKHR::out(std::ptr::null_mut()).assume_init();
+            let result =
+                (vulkan.get_vulkan_graphics_requirements)(xr_instance, system_id, &mut reqs);
+            if result.result().is_err() {
+                error!("get_vulkan_graphics_requirements returned: {}", result);
+                return Err(());
+            }
+            reqs
         } else {
-            todo!()
+            error!("Runtime supports neither XR_KHR_vulkan_enable2 nor XR_KHR_vulkan_enable");
+            return Err(());
         };
 
-        //TODO actually check requirements
+        //Patch versions don't matter for compatibility
+        let api_version = (1, 1);
+        let version = |version: xr::Version| (version.major(), version.minor());
+        if api_version < version(requirements.min_api_version_supported) {
+            error!(
+                "Runtime requires at least Vulkan {}, the layer uses 1.1",
+                requirements.min_api_version_supported
+            );
+            return Err(());
+        }
+        if api_version > version(requirements.max_api_version_supported) {
+            warn!(
+                "Runtime supports up to Vulkan {}, the layer uses 1.1",
+                requirements.max_api_version_supported
+            );
+        }
 
         let layer_names = [CStr::from_bytes_with_nul_unchecked(
             b"VK_LAYER_KHRONOS_validation\0",
         )];
-        let _layers_names_raw: Vec<*const c_char> = layer_names
+        let layers_names_raw: Vec<*const c_char> = layer_names
             .iter()
             .map(|raw_name| raw_name.as_ptr())
             .collect();
 
-        let instance_extensions = [DebugUtils::name().as_ptr()];
+        //With XR_KHR_vulkan_enable the runtime only tells us which extensions it needs
+        let runtime_instance_extensions = match exts.khr_vulkan_enable {
+            Some(vulkan) if exts.khr_vulkan_enable2.is_none() => {
+                match xr_vulkan_extensions(
+                    vulkan.get_vulkan_instance_extensions,
+                    xr_instance,
+                    system_id,
+                ) {
+                    Ok(extensions) => extensions,
+                    Err(result) => {
+                        error!("get_vulkan_instance_extensions returned: {}", result);
+                        return Err(());
+                    }
+                }
+            }
+            _ => Vec::new(),
+        };
+
+        let mut instance_extensions = vec![DebugUtils::name().as_ptr()];
+        for extension in &runtime_instance_extensions {
+            if extension.as_c_str() != DebugUtils::name() {
+                instance_extensions.push(extension.as_ptr());
+            }
+        }
 
         let app_info = vk::ApplicationInfo::builder()
             .application_name(CStr::from_bytes_with_nul_unchecked(b"SorenonOpenXRLayer\0"))
             .application_version(0)
-            .api_version(vk::make_api_version(0, 1, 1, 0));
+            .api_version(vk::make_api_version(
+                0,
+                api_version.0 as u32,
+                api_version.1 as u32,
+                0,
+            ));
 
         let instance_info = vk::InstanceCreateInfo::builder()
             .application_info(&app_info)
             .enabled_extension_names(&instance_extensions);
 
-        // let instance_info = if option_env!("SORENON_LAYER_VK_VALIDATION").is_some() {
-        //     instance_info.enabled_layer_names(&layers_names_raw)
-        // } else {
-        //     instance_info
-        // };
+        let instance_info = if validation {
+            instance_info.enabled_layer_names(&layers_names_raw)
+        } else {
+            instance_info
+        };
 
         let vk_instance = if let Some(vulkan) = exts.khr_vulkan_enable2 {
             let mut vk_instance = vk::Instance::null();
@@ -113,7 +238,13 @@ impl VkBackend {
 
             Instance::load(entry.static_fn(), vk_instance)
         } else {
-            todo!()
+            match entry.create_instance(&instance_info, None) {
+                Ok(instance) => instance,
+                Err(result) => {
+                    error!("Vulkan error creating vulkan instance: {}", result);
+                    return Err(());
+                }
+            }
         };
 
         let (debug_utils, debug_messenger) = create_debug_callback(&entry, &vk_instance).unwrap();
@@ -135,14 +266,28 @@ impl VkBackend {
                 return Err(());
             }
             physical_device
+        } else if let Some(vulkan) = exts.khr_vulkan_enable {
+            let mut physical_device = vk::PhysicalDevice::null();
+            let result = (vulkan.get_vulkan_graphics_device)(
+                xr_instance,
+                system_id,
+                vk_instance.handle().as_raw() as _,
+                &mut physical_device as *mut _ as _,
+            );
+            if result.result().is_err() {
+                error!("OpenXR error getting physical device: {}", result);
+                return Err(());
+            }
+            physical_device
         } else {
-            todo!()
+            error!("Runtime supports neither XR_KHR_vulkan_enable2 nor XR_KHR_vulkan_enable");
+            return Err(());
         };
 
         let device_memory_properties =
             vk_instance.get_physical_device_memory_properties(physical_device);
 
-        let graphics_queue_family = vk_instance
+        let graphics_queue_family = match vk_instance
             .get_physical_device_queue_family_properties(physical_device)
             .into_iter()
             .enumerate()
@@ -152,12 +297,42 @@ impl VkBackend {
                 } else {
                     None
                 }
-            })
-            .expect("Vulkan device has no graphics queue");
+            }) {
+            Some(queue_family) => queue_family,
+            None => {
+                error!("Vulkan device has no graphics queue");
+                return Err(());
+            }
+        };
 
         let mut device_extension_names = graphics_interop::apis::vulkan::needed_device_extensions();
         device_extension_names.push(vk::ExtShaderViewportIndexLayerFn::name().as_ptr());
 
+        let runtime_device_extensions = match exts.khr_vulkan_enable {
+            Some(vulkan) if exts.khr_vulkan_enable2.is_none() => {
+                match xr_vulkan_extensions(
+                    vulkan.get_vulkan_device_extensions,
+                    xr_instance,
+                    system_id,
+                ) {
+                    Ok(extensions) => extensions,
+                    Err(result) => {
+                        error!("get_vulkan_device_extensions returned: {}", result);
+                        return Err(());
+                    }
+                }
+            }
+            _ => Vec::new(),
+        };
+        for extension in &runtime_device_extensions {
+            if !device_extension_names
+                .iter()
+                .any(|&name| CStr::from_ptr(name) == extension.as_c_str())
+            {
+                device_extension_names.push(extension.as_ptr());
+            }
+        }
+
         let queue_info = vk::DeviceQueueCreateInfo::builder()
             .queue_family_index(graphics_queue_family)
             .queue_priorities(&[1.0]);
@@ -204,7 +379,13 @@ impl VkBackend {
 
             Device::load(vk_instance.fp_v1_0(), device)
         } else {
-            todo!()
+            match vk_instance.create_device(physical_device, &device_info, None) {
+                Ok(device) => device,
+                Err(result) => {
+                    error!("Vulkan error creating vulkan device: {}", result);
+                    return Err(());
+                }
+            }
         };
 
         let graphics_queue = device.get_device_queue(graphics_queue_family, 0);
@@ -226,6 +407,19 @@ impl VkBackend {
         }
         .unwrap();
 
+        //Clamped so the edges don't blend with the opposite side
+        let linear_sampler = {
+            let create_info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST);
+            device.create_sampler(&create_info, None)
+        }
+        .unwrap();
+
         let descriptor_set_layout = {
             let sampler_layout_binding = vk::DescriptorSetLayoutBinding {
                 binding: 0,
@@ -240,6 +434,29 @@ impl VkBackend {
         }
         .unwrap();
 
+        //The conversion shader samples through a separate image and sampler
+        let convert_descriptor_set_layout = {
+            let bindings = [
+                vk::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                    ..Default::default()
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    descriptor_type: vk::DescriptorType::SAMPLER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                    ..Default::default()
+                },
+            ];
+            let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+            device.create_descriptor_set_layout(&layout_info, None)
+        }
+        .unwrap();
+
         let interop = VulkanInterop::new(&vk_instance, physical_device, &device);
 
         Ok(VkBackend {
@@ -254,11 +471,89 @@ impl VkBackend {
             graphics_queue,
             command_pool,
             nearest_sampler,
+            linear_sampler,
             descriptor_set_layout,
+            convert_descriptor_set_layout,
             interop,
+            queued_copies: Default::default(),
+            pipelines: Default::default(),
         })
     }
 
+    /// Submits every queued copy in one batch
+    pub fn submit_queued_copies(&self) -> VkResult<()> {
+        let copies = std::mem::take(&mut *self.queued_copies.lock().unwrap());
+        let (first, rest) = match copies.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let wait_semaphores = copies
+            .iter()
+            .filter_map(|copy| copy.wait_semaphore)
+            .collect::<Vec<_>>();
+        let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+        let command_buffers = copies
+            .iter()
+            .map(|copy| copy.command_buffer)
+            .collect::<Vec<_>>();
+        //The fences were reset when the images were held
+        if let Err(result) = unsafe {
+            self.device.queue_submit(
+                self.graphics_queue,
+                slice::from_ref(
+                    &vk::SubmitInfo::builder()
+                        .wait_semaphores(&wait_semaphores)
+                        .wait_dst_stage_mask(&wait_stages)
+                        .command_buffers(&command_buffers),
+                ),
+                first.fence,
+            )
+        } {
+            self.skip_copies(&copies);
+            return Err(result);
+        }
+        //A submission can only signal one fence, empty ones signal theirs once everything
+        //submitted before them is done
+        let mut result = Ok(());
+        for copy in rest {
+            if let Err(error) = unsafe {
+                self.device
+                    .queue_submit(self.graphics_queue, &[], copy.fence)
+            } {
+                result = Err(error);
+            }
+        }
+        result
+    }
+
+    /// Signals the fences of copies that failed to submit without copying, anything waiting on
+    /// them would wait forever otherwise
+    ///
+    /// If even this fails the device is lost, and waits on lost devices return on their own.
+    pub fn skip_copies(&self, copies: &[QueuedCopy]) {
+        for copy in copies {
+            //Consumes the semaphore so OpenGL can signal it again
+            let wait_semaphores = copy
+                .wait_semaphore
+                .as_ref()
+                .map_or(&[][..], slice::from_ref);
+            let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+            if let Err(result) = unsafe {
+                self.device.queue_submit(
+                    self.graphics_queue,
+                    slice::from_ref(
+                        &vk::SubmitInfo::builder()
+                            .wait_semaphores(wait_semaphores)
+                            .wait_dst_stage_mask(&wait_stages[..wait_semaphores.len()]),
+                    ),
+                    copy.fence,
+                )
+            } {
+                error!("Failed to signal a copy's fence: {}", result);
+            }
+        }
+    }
+
     pub fn find_memorytype_index(
         &self,
         memory_req: &vk::MemoryRequirements,
@@ -275,6 +570,33 @@ impl VkBackend {
             .map(|(index, _memory_type)| index as _)
     }
 
+    /// Whether the device can blit images of `src` into images of `dst`
+    pub fn can_blit(&self, src: vk::Format, dst: vk::Format) -> bool {
+        //Depth and stencil formats can only be blitted into the same format
+        if src != dst && (is_depth_stencil(src) || is_depth_stencil(dst)) {
+            return false;
+        }
+        let features = |format| {
+            unsafe {
+                self.instance
+                    .get_physical_device_format_properties(self.physical_device, format)
+            }
+            .optimal_tiling_features
+        };
+        features(src).contains(vk::FormatFeatureFlags::BLIT_SRC)
+            && features(dst).contains(vk::FormatFeatureFlags::BLIT_DST)
+    }
+
+    /// Whether the device can sample images of `format` with a linear filter
+    pub fn can_filter_linear(&self, format: vk::Format) -> bool {
+        unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        }
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR)
+    }
+
     pub fn create_image_view(
         &self,
         image: vk::Image,
@@ -301,17 +623,84 @@ impl VkBackend {
         unsafe { self.device.create_image_view(&create_info, None) }
     }
 
-    pub unsafe fn create_graphics_pipeline(
+    /// Creates a device local 2D array image for the layer's own use
+    pub fn create_local_image(
         &self,
+        format: vk::Format,
         width: u32,
         height: u32,
+        layers: u32,
+        usage: vk::ImageUsageFlags,
+        flags: vk::ImageCreateFlags,
+    ) -> VkResult<(vk::Image, vk::DeviceMemory)> {
+        let create_info = vk::ImageCreateInfo::builder()
+            .flags(flags)
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(layers)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        unsafe {
+            let image = self.device.create_image(&create_info, None)?;
+            let memory_req = self.device.get_image_memory_requirements(image);
+            let memory_type_index = self
+                .find_memorytype_index(&memory_req, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+                .ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)?;
+            let allocate_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(memory_req.size)
+                .memory_type_index(memory_type_index);
+            let memory = self.device.allocate_memory(&allocate_info, None)?;
+            self.device.bind_image_memory(image, memory, 0)?;
+            Ok((image, memory))
+        }
+    }
+
+    /// The draw pipeline rendering into `format`, created the first time it is asked for
+    ///
+    /// With `convert` the pipeline uses `convert_descriptor_set_layout` and `ConvertConstants`,
+    /// `descriptor_set_layout` otherwise. Viewport and scissor are dynamic.
+    pub fn pipeline(
+        &self,
         format: vk::Format,
         sample_count: vk::SampleCountFlags,
-        descriptor_set_layouts: &[vk::DescriptorSetLayout],
-    ) -> (vk::PipelineLayout, vk::RenderPass, vk::Pipeline) {
+        convert: bool,
+    ) -> VkResult<DrawPipeline> {
+        let key = PipelineKey {
+            format,
+            sample_count,
+            convert,
+        };
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(&pipeline) = pipelines.get(&key) {
+            return Ok(pipeline);
+        }
+        let pipeline = unsafe { self.create_graphics_pipeline(key) }?;
+        pipelines.insert(key, pipeline);
+        Ok(pipeline)
+    }
+
+    unsafe fn create_graphics_pipeline(&self, key: PipelineKey) -> VkResult<DrawPipeline> {
+        let PipelineKey {
+            format,
+            sample_count,
+            convert,
+        } = key;
         let device = &self.device;
-        let vert_shader = create_shader_module(device, VERTEX).unwrap();
-        let frag_shader = create_shader_module(device, FRAGMENT).unwrap();
+        let vert_shader = create_shader_module(device, VERTEX)?;
+        let frag_shader = create_shader_module(device, if convert { CONVERT } else { FRAGMENT })
+            .map_err(|result| {
+                device.destroy_shader_module(vert_shader, None);
+                result
+            })?;
 
         let shader_stages = [
             vk::PipelineShaderStageCreateInfo::builder()
@@ -331,24 +720,13 @@ impl VkBackend {
         let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
 
-        let viewport = vk::Viewport {
-            x: 0.,
-            y: 0.,
-            width: width as f32,
-            height: height as f32,
-            min_depth: 0.,
-            max_depth: 1.,
-        };
-
-        let scissor = vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: vk::Extent2D { width, height },
-        };
-
         let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(slice::from_ref(&viewport))
-            .scissors(slice::from_ref(&scissor))
-            .build();
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
 
         let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
             .polygon_mode(vk::PolygonMode::FILL)
@@ -366,49 +744,90 @@ impl VkBackend {
         let color_blending =
             vk::PipelineColorBlendStateCreateInfo::builder().attachments(&color_blend_attachments);
 
-        let layout = device
-            .create_pipeline_layout(
-                &vk::PipelineLayoutCreateInfo::builder().set_layouts(descriptor_set_layouts),
-                None,
-            )
-            .unwrap();
-
-        let render_pass = create_render_pass(device, format, sample_count).unwrap();
-
-        //TODO VK_PIPELINE_CREATE_DERIVATIVE_BIT
-        let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
-            .stages(&shader_stages)
-            .vertex_input_state(&vertex_input_info)
-            .input_assembly_state(&input_assembly)
-            .viewport_state(&viewport_state)
-            .rasterization_state(&rasterizer)
-            .multisample_state(&multisampling)
-            .color_blend_state(&color_blending)
-            .layout(layout)
-            .render_pass(render_pass)
-            .subpass(0)
-            .build();
-
-        let pipeline = *device
-            .create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                slice::from_ref(&pipeline_info),
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: std::mem::size_of::<ConvertConstants>() as u32,
+        };
+        let push_constant_ranges = if convert {
+            slice::from_ref(&push_constant_range)
+        } else {
+            &[]
+        };
+
+        let descriptor_set_layout = if convert {
+            self.convert_descriptor_set_layout
+        } else {
+            self.descriptor_set_layout
+        };
+        let mut draw_pipeline = DrawPipeline {
+            layout: vk::PipelineLayout::null(),
+            render_pass: vk::RenderPass::null(),
+            pipeline: vk::Pipeline::null(),
+        };
+        let result = (|| -> VkResult<()> {
+            draw_pipeline.layout = device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::builder()
+                    .set_layouts(slice::from_ref(&descriptor_set_layout))
+                    .push_constant_ranges(push_constant_ranges),
                 None,
-            )
-            .unwrap()
-            .first()
-            .unwrap();
+            )?;
+
+            draw_pipeline.render_pass = create_render_pass(device, format, sample_count)?;
+
+            //TODO VK_PIPELINE_CREATE_DERIVATIVE_BIT
+            let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+                .stages(&shader_stages)
+                .vertex_input_state(&vertex_input_info)
+                .input_assembly_state(&input_assembly)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterizer)
+                .multisample_state(&multisampling)
+                .color_blend_state(&color_blending)
+                .dynamic_state(&dynamic_state)
+                .layout(draw_pipeline.layout)
+                .render_pass(draw_pipeline.render_pass)
+                .subpass(0)
+                .build();
+
+            draw_pipeline.pipeline = device
+                .create_graphics_pipelines(
+                    vk::PipelineCache::null(),
+                    slice::from_ref(&pipeline_info),
+                    None,
+                )
+                .map_err(|(_, result)| result)?[0];
+            Ok(())
+        })();
 
         device.destroy_shader_module(vert_shader, None);
         device.destroy_shader_module(frag_shader, None);
 
-        (layout, render_pass, pipeline)
+        match result {
+            Ok(()) => Ok(draw_pipeline),
+            Err(result) => {
+                draw_pipeline.destroy(device);
+                Err(result)
+            }
+        }
     }
 }
 
 impl Drop for VkBackend {
     fn drop(&mut self) {
         unsafe {
+            if let Err(result) = self.device.device_wait_idle() {
+                error!("Failed to wait for the vulkan device: {}", result);
+            }
+            for pipeline in self.pipelines.get_mut().unwrap().values() {
+                pipeline.destroy(&self.device);
+            }
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.device
+                .destroy_descriptor_set_layout(self.convert_descriptor_set_layout, None);
+            self.device.destroy_sampler(self.nearest_sampler, None);
+            self.device.destroy_sampler(self.linear_sampler, None);
             self.device.destroy_command_pool(self.command_pool, None);
             self.device.destroy_device(None);
             self.debug_utils
@@ -481,8 +900,51 @@ unsafe fn create_command_pool(device: &Device, queue_family: u32) -> VkResult<vk
     device.create_command_pool(&pool_create_info, None)
 }
 
+fn is_depth_stencil(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM
+            | vk::Format::X8_D24_UNORM_PACK32
+            | vk::Format::D32_SFLOAT
+            | vk::Format::S8_UINT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+/// Reads the space separated extension list returned by the XR_KHR_vulkan_enable functions
+unsafe fn xr_vulkan_extensions(
+    f: xr::pfn::GetVulkanInstanceExtensionsKHR,
+    instance: xr::Instance,
+    system_id: xr::SystemId,
+) -> Result<Vec<CString>, xr::Result> {
+    let mut len = 0;
+    f(instance, system_id, 0, &mut len, std::ptr::null_mut()).result()?;
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut buffer = vec![0 as c_char; len as usize];
+    f(instance, system_id, len, &mut len, buffer.as_mut_ptr()).result()?;
+
+    //The length includes the terminator, which isn't relied on
+    let buffer = buffer[..(len as usize).min(buffer.len())]
+        .iter()
+        .map(|&c| c as u8)
+        .take_while(|&c| c != 0)
+        .collect::<Vec<_>>();
+    let buffer = String::from_utf8_lossy(&buffer);
+    Ok(buffer
+        .split(' ')
+        .filter(|name| !name.is_empty())
+        .map(|name| CString::new(name).unwrap())
+        .collect())
+}
+
 const VERTEX: &[u8] = include_bytes!("../../../shaders/vert.spv");
 const FRAGMENT: &[u8] = include_bytes!("../../../shaders/frag.spv");
+const CONVERT: &[u8] = include_bytes!("../../../shaders/convert.spv");
 
 unsafe fn create_shader_module(device: &Device, code_bytes: &[u8]) -> VkResult<vk::ShaderModule> {
     let shader_code = ash::util::read_spv(&mut Cursor::new(code_bytes)).unwrap();
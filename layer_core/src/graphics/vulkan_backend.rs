@@ -1,250 +1,566 @@
 use core::slice;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use ash::{
     prelude::VkResult,
     vk::{self, Handle},
 };
+use graphics_interop::{ImageFormat, ImageLayout, ImageUsage, ViewFormats};
+use log::error;
 use openxr::sys as xr;
 
-use crate::wrappers::{instance::InnerInstance, swapchain::SwapchainBackend};
+use crate::settings::ToneCurve;
+use crate::wrappers::{
+    instance::InnerInstance,
+    swapchain::{AlphaConversion, SwapchainBackend},
+};
 
-use super::vulkan::VkBackend;
+use super::vulkan::{ConvertConstants, DrawPipeline, QueuedCopy, VkBackend};
 
 pub struct SwapchainBackendVulkan {
     vk_backend: Arc<VkBackend>,
+    image_info: graphics_interop::ImageCreateInfo,
+    runtime_format: ImageFormat,
+    /// Larger than the interop images when upscaling
+    runtime_extent: vk::Extent2D,
+    /// How the copy samples the interop images when scaling them
+    filter: vk::Filter,
+    mode: CopyMode,
+    tone_mapping: ToneMapping,
+    /// The layout the interop images are in whenever OpenGL hands them over
+    interop_layout: vk::ImageLayout,
     images: Vec<vk::Image>,
     memory: Vec<(vk::DeviceMemory, u64)>,
+    /// Single sample images the multisampled interop images are resolved into before the copy
+    resolve_images: Vec<(vk::Image, vk::DeviceMemory)>,
+    /// The images the draw reads, the resolve images when multisampling
+    sampled_images: Vec<vk::Image>,
     runtime_images: Vec<vk::Image>,
-    pipeline: vk::Pipeline,
-    pipeline_layout: vk::PipelineLayout,
-    render_pass: vk::RenderPass,
+    /// Signalled by OpenGL when it is done with an image, empty if OpenGL can't import them
+    semaphores: Vec<vk::Semaphore>,
+    command_buffers: Vec<vk::CommandBuffer>,
+    /// Signalled when an image's last copy is done, they start out signalled
+    fences: Vec<vk::Fence>,
+    /// Held while the command buffers are recorded or submitted
+    recording: Mutex<Recording>,
+}
+
+/// What the command buffers were last recorded with
+struct Recording {
+    alpha: AlphaConversion,
+    draw: Option<DrawResources>,
+    /// Images whose copy waits for the session's batch, their fences are already reset
+    held: Vec<bool>,
+}
+
+/// How the interop images are copied into the runtime images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyMode {
+    /// A fullscreen draw flips level 0, any levels above it are flipped with blits
+    Draw,
+    /// Every level is blitted, converting between formats if they differ
+    Blit { flip: bool },
+    /// Every level is copied as is
+    Copy,
+}
+
+impl CopyMode {
+    /// Picks the cheapest copy for a swapchain, `alpha_conversion` is whether its alpha may have to
+    /// be converted later on
+    pub fn new(
+        vk_backend: &VkBackend,
+        image_info: &graphics_interop::ImageCreateInfo,
+        runtime_format: ImageFormat,
+        alpha_conversion: bool,
+    ) -> Self {
+        if image_info.faces == 6 {
+            //Both APIs address cubemap faces the same way so they need no flip
+            if image_info.format == runtime_format {
+                CopyMode::Copy
+            } else {
+                CopyMode::Blit { flip: false }
+            }
+        } else if image_info.format.is_depth() {
+            CopyMode::Blit { flip: true }
+        } else if image_info.sample_count == 1
+            && !image_info.format.needs_tone_mapping(runtime_format)
+            && !alpha_conversion
+            && vk_backend.can_blit(
+                image_info.format.to_vk().unwrap(),
+                runtime_format.to_vk().unwrap(),
+            )
+        {
+            //Blits convert sRGB and channel order the same way the draw's views do
+            CopyMode::Blit { flip: true }
+        } else {
+            CopyMode::Draw
+        }
+    }
+}
+
+/// The runtime swapchain a swapchain is copied into
+#[derive(Debug, Clone, Copy)]
+pub struct RuntimeTarget {
+    pub format: ImageFormat,
+    /// Larger than the application's swapchain when upscaling
+    pub extent: vk::Extent2D,
+}
+
+/// How float swapchains are brought into the range of a runtime format without floats
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapping {
+    pub curve: ToneCurve,
+    pub dither: bool,
+}
+
+/// The conversion shader's push constants, `tone_mapping` is only given when the source needs it
+fn convert_constants(
+    tone_mapping: Option<ToneMapping>,
+    views: ViewFormats,
+    runtime_format: ImageFormat,
+    alpha: AlphaConversion,
+) -> ConvertConstants {
+    let bits = runtime_format.info().bits_per_channel;
+    ConvertConstants {
+        curve: match tone_mapping.map(|tone_mapping| tone_mapping.curve) {
+            None => 0,
+            Some(ToneCurve::Clamp) => 1,
+            Some(ToneCurve::Reinhard) => 2,
+            Some(ToneCurve::Aces) => 3,
+        },
+        //Tone mapping renders through a linear view and encodes sRGB itself
+        encode_srgb: (views.rendered != runtime_format) as u32,
+        dither_scale: if tone_mapping.is_some_and(|tone_mapping| tone_mapping.dither) {
+            1.0 / ((1u32 << bits) - 1) as f32
+        } else {
+            0.0
+        },
+        alpha: alpha.bits(),
+    }
+}
+
+/// Everything the fullscreen draw needs
+struct DrawResources {
+    /// Shared with other swapchains and owned by the `VkBackend`
+    pipeline: DrawPipeline,
+    /// The size of the runtime images rendered into
+    extent: vk::Extent2D,
     image_views: Vec<vk::ImageView>,
     runtime_image_views: Vec<vk::ImageView>,
     framebuffers: Vec<vk::Framebuffer>,
-    command_buffers: Vec<vk::CommandBuffer>,
     descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    convert: Option<ConvertConstants>,
 }
 
 impl SwapchainBackendVulkan {
+    /// Usage the copy needs on the interop images on top of what the application asked for
+    pub fn interop_usage(
+        image_info: &graphics_interop::ImageCreateInfo,
+        runtime_format: ImageFormat,
+        mode: CopyMode,
+    ) -> ImageUsage {
+        if mode != CopyMode::Draw {
+            return ImageUsage::TRANSFER_SRC;
+        }
+        //Multisampled images are sampled through the resolve images instead
+        let mut usage = ImageUsage::TRANSFER_SRC;
+        if image_info.sample_count == 1 {
+            usage |= ImageUsage::SAMPLED;
+            if ViewFormats::new(image_info.format, runtime_format).mutable {
+                usage |= ImageUsage::MUTABLE_FORMAT;
+            }
+        }
+        usage
+    }
+
+    /// Usage the copy needs on the runtime images on top of what the application asked for
+    pub fn runtime_usage(
+        image_info: &graphics_interop::ImageCreateInfo,
+        runtime_format: ImageFormat,
+        mode: CopyMode,
+    ) -> xr::SwapchainUsageFlags {
+        //The runtime gets its images back as attachments
+        let mut usage = if image_info.format.is_depth() {
+            xr::SwapchainUsageFlags::DEPTH_STENCIL_ATTACHMENT
+        } else {
+            xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+        };
+        if mode != CopyMode::Draw || image_info.mip_count > 1 {
+            usage |= xr::SwapchainUsageFlags::TRANSFER_DST;
+        }
+        if mode == CopyMode::Draw && ViewFormats::new(image_info.format, runtime_format).mutable {
+            usage |= xr::SwapchainUsageFlags::MUTABLE_FORMAT;
+        }
+        usage
+    }
+
+    /// The layout the interop images are kept in, which OpenGL has to hand them over in
+    pub fn interop_layout(
+        image_info: &graphics_interop::ImageCreateInfo,
+        mode: CopyMode,
+    ) -> ImageLayout {
+        match mode {
+            //Multisampled images are only resolved
+            CopyMode::Draw if image_info.sample_count > 1 => ImageLayout::TransferSrc,
+            //Level 0 is sampled while the levels above it are blitted
+            CopyMode::Draw if image_info.mip_count > 1 => ImageLayout::General,
+            CopyMode::Draw => ImageLayout::ShaderReadOnly,
+            CopyMode::Blit { .. } | CopyMode::Copy => ImageLayout::TransferSrc,
+        }
+    }
+
+    /// Whether the copy uses blits, which not every format supports
+    pub fn uses_blits(image_info: &graphics_interop::ImageCreateInfo, mode: CopyMode) -> bool {
+        match mode {
+            CopyMode::Draw => image_info.mip_count > 1,
+            CopyMode::Blit { .. } => true,
+            CopyMode::Copy => false,
+        }
+    }
+
     pub fn load(
         swapchain: xr::Swapchain,
         inner: &InnerInstance,
         vk_backend: Arc<VkBackend>,
         image_info: &graphics_interop::ImageCreateInfo,
-    ) -> Self {
+        runtime: RuntimeTarget,
+        mode: CopyMode,
+        tone_mapping: ToneMapping,
+        semaphores: bool,
+    ) -> openxr::Result<Self> {
         let runtime_images = unsafe {
             crate::interceptors::call_enumerate(
                 swapchain,
                 std::mem::transmute(inner.core.enumerate_swapchain_images),
                 xr::SwapchainImageVulkanKHR::out(std::ptr::null_mut()).assume_init(),
             )
-        }
-        .unwrap()
+        }?
         .into_iter()
         .map(|image| vk::Image::from_raw(image.image))
         .collect::<Vec<_>>();
 
-        let mut memory = Vec::with_capacity(runtime_images.len());
-        let mut images = Vec::with_capacity(runtime_images.len());
+        //Depth can't be filtered, and neither can formats the device doesn't filter
+        let scaled =
+            runtime.extent.width != image_info.width || runtime.extent.height != image_info.height;
+        let filter = if scaled
+            && !image_info.format.is_depth()
+            && image_info
+                .format
+                .to_vk()
+                .is_some_and(|format| vk_backend.can_filter_linear(format))
+        {
+            vk::Filter::LINEAR
+        } else {
+            vk::Filter::NEAREST
+        };
 
-        let cb_memory_barrier = unsafe {
-            *vk_backend
-                .device
-                .allocate_command_buffers(&vk::CommandBufferAllocateInfo {
-                    command_pool: vk_backend.command_pool,
-                    level: vk::CommandBufferLevel::PRIMARY,
-                    command_buffer_count: 1,
-                    ..Default::default()
-                })
-                .unwrap()
-                .first()
-                .unwrap()
+        let mut backend = Self {
+            vk_backend,
+            image_info: *image_info,
+            runtime_format: runtime.format,
+            runtime_extent: runtime.extent,
+            filter,
+            mode,
+            tone_mapping,
+            interop_layout: Self::interop_layout(image_info, mode).to_vk(),
+            images: Vec::with_capacity(runtime_images.len()),
+            memory: Vec::with_capacity(runtime_images.len()),
+            resolve_images: Vec::new(),
+            sampled_images: Vec::new(),
+            runtime_images,
+            semaphores: Vec::new(),
+            command_buffers: Vec::new(),
+            fences: Vec::new(),
+            recording: Mutex::new(Recording {
+                alpha: AlphaConversion::empty(),
+                draw: None,
+                held: Vec::new(),
+            }),
         };
-        unsafe {
-            vk_backend
-                .device
-                .begin_command_buffer(
-                    cb_memory_barrier,
-                    &vk::CommandBufferBeginInfo {
-                        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
-                        ..Default::default()
-                    },
-                )
-                .unwrap();
+        //Whatever was created before a failure is destroyed again
+        if let Err(result) = backend.create(semaphores) {
+            error!(
+                "Failed to create the copy resources of {:?}: {}",
+                swapchain, result
+            );
+            backend.destroy();
+            return Err(match result {
+                vk::Result::ERROR_FORMAT_NOT_SUPPORTED => {
+                    xr::Result::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED
+                }
+                _ => xr::Result::ERROR_RUNTIME_FAILURE,
+            });
         }
+        Ok(backend)
+    }
 
-        for i in 0..runtime_images.len() {
-            images.push(
-                vk_backend
-                    .interop
-                    .create_external_image(image_info)
-                    .unwrap(),
-            );
-            memory.push(
-                vk_backend
-                    .interop
-                    .alloc_and_bind_external_image(images[i])
-                    .unwrap(),
-            );
+    fn create(&mut self, semaphores: bool) -> VkResult<()> {
+        let vk_backend = self.vk_backend.clone();
+        let image_info = self.image_info;
+        let image_count = self.runtime_images.len();
+        let views = ViewFormats::new(image_info.format, self.runtime_format);
+        let multisampled = image_info.sample_count > 1;
+        let layers = image_info.array_layers();
 
-            unsafe {
-                vk_backend.device.cmd_pipeline_barrier(
-                    cb_memory_barrier,
-                    vk::PipelineStageFlags::TOP_OF_PIPE,
-                    vk::PipelineStageFlags::TOP_OF_PIPE,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &[vk::ImageMemoryBarrier {
-                        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                        image: images[i],
-                        subresource_range: vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            level_count: 1,
-                            layer_count: image_info.layers,
-                            ..Default::default()
+        for _ in 0..image_count {
+            let image = vk_backend.interop.create_external_image(&image_info)?;
+            self.images.push(image);
+            self.memory
+                .push(vk_backend.interop.alloc_and_bind_external_image(image)?);
+
+            if multisampled {
+                self.resolve_images.push(
+                    vk_backend.create_local_image(
+                        image_info
+                            .format
+                            .to_vk()
+                            .ok_or(vk::Result::ERROR_FORMAT_NOT_SUPPORTED)?,
+                        image_info.width,
+                        image_info.height,
+                        layers,
+                        vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                        if views.mutable {
+                            vk::ImageCreateFlags::MUTABLE_FORMAT
+                        } else {
+                            vk::ImageCreateFlags::empty()
                         },
-                        ..Default::default()
-                    }],
+                    )?,
                 );
             }
         }
 
-        unsafe {
-            vk_backend
-                .device
-                .end_command_buffer(cb_memory_barrier)
-                .unwrap();
-            vk_backend
-                .device
-                .queue_submit(
-                    vk_backend.graphics_queue,
-                    &[vk::SubmitInfo::builder()
-                        .command_buffers(std::slice::from_ref(&cb_memory_barrier))
-                        .build()],
-                    vk::Fence::null(),
-                )
-                .unwrap();
+        let cb_memory_barrier = unsafe {
             vk_backend
                 .device
-                .queue_wait_idle(vk_backend.graphics_queue)
-                .unwrap();
+                .allocate_command_buffers(&vk::CommandBufferAllocateInfo {
+                    command_pool: vk_backend.command_pool,
+                    level: vk::CommandBufferLevel::PRIMARY,
+                    command_buffer_count: 1,
+                    ..Default::default()
+                })
+        }?[0];
+        let result = unsafe { self.transition_images(cb_memory_barrier) };
+        unsafe {
             vk_backend
                 .device
                 .free_command_buffers(vk_backend.command_pool, &[cb_memory_barrier]);
         }
+        result?;
 
-        let (pipeline_layout, render_pass, pipeline) = unsafe {
-            vk_backend.create_graphics_pipeline(
-                image_info.width,
-                image_info.height,
-                image_info.format.to_vk().unwrap(),
-                vk::SampleCountFlags::TYPE_1,
-                std::slice::from_ref(&vk_backend.descriptor_set_layout),
-            )
+        //The draw samples from the resolved images when multisampling
+        self.sampled_images = if multisampled {
+            self.resolve_images
+                .iter()
+                .map(|&(image, _)| image)
+                .collect()
+        } else {
+            self.images.clone()
         };
 
-        let image_views = images
-            .iter()
-            .map(|&image| {
-                vk_backend.create_image_view(
-                    image,
-                    image_info.format.to_vk().unwrap(),
-                    image_info.layers,
-                )
-            })
-            .collect::<VkResult<Vec<_>>>()
-            .unwrap();
-
-        let runtime_image_views = runtime_images
-            .iter()
-            .map(|&image| {
-                vk_backend.create_image_view(
-                    image,
-                    image_info.format.to_vk().unwrap(),
-                    image_info.layers,
-                )
-            })
-            .collect::<VkResult<Vec<_>>>()
-            .unwrap();
-
-        let framebuffers = runtime_image_views
-            .iter()
-            .map(|image_view| {
-                let create_info = vk::FramebufferCreateInfo::builder()
-                    .render_pass(render_pass)
-                    .attachments(std::slice::from_ref(image_view))
-                    .width(image_info.width)
-                    .height(image_info.height)
-                    .layers(image_info.layers);
-                unsafe { vk_backend.device.create_framebuffer(&create_info, None) }
-            })
-            .collect::<VkResult<Vec<_>>>()
-            .unwrap();
+        if semaphores {
+            for _ in 0..image_count {
+                self.semaphores
+                    .push(vk_backend.interop.create_external_semaphore()?);
+            }
+        }
 
-        let command_buffers = unsafe {
+        self.command_buffers = unsafe {
             vk_backend.device.allocate_command_buffers(
                 &vk::CommandBufferAllocateInfo::builder()
                     .command_pool(vk_backend.command_pool)
                     .level(vk::CommandBufferLevel::PRIMARY)
-                    .command_buffer_count(framebuffers.len() as u32),
+                    .command_buffer_count(image_count as u32),
             )
+        }?;
+
+        for _ in 0..image_count {
+            self.fences.push(unsafe {
+                vk_backend.device.create_fence(
+                    &vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED),
+                    None,
+                )
+            }?);
         }
-        .unwrap();
 
-        let descriptor_pool = unsafe {
-            let descriptor_size = vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: image_views.len() as u32,
-            };
-            let create_info = vk::DescriptorPoolCreateInfo::builder()
-                .pool_sizes(std::slice::from_ref(&descriptor_size))
-                .max_sets(image_views.len() as u32);
+        let mut recording = self.recording.lock().unwrap();
+        recording.held = vec![false; image_count];
+        self.record(&mut recording, AlphaConversion::empty())
+    }
 
-            vk_backend.device.create_descriptor_pool(&create_info, None)
+    /// Moves the interop and resolve images into the layouts the copy expects them in
+    unsafe fn transition_images(&self, command_buffer: vk::CommandBuffer) -> VkResult<()> {
+        let device = &self.vk_backend.device;
+        let image_info = &self.image_info;
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: image_info.format.vk_aspects(),
+            level_count: 1,
+            layer_count: image_info.array_layers(),
+            ..Default::default()
+        };
+
+        device.begin_command_buffer(
+            command_buffer,
+            &vk::CommandBufferBeginInfo {
+                flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                ..Default::default()
+            },
+        )?;
+
+        for (i, &image) in self.images.iter().enumerate() {
+            let mut barriers = vec![vk::ImageMemoryBarrier {
+                new_layout: self.interop_layout,
+                image,
+                subresource_range: vk::ImageSubresourceRange {
+                    level_count: image_info.mip_count,
+                    ..subresource_range
+                },
+                ..Default::default()
+            }];
+
+            if let Some(&(resolve_image, _)) = self.resolve_images.get(i) {
+                barriers.push(vk::ImageMemoryBarrier {
+                    new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    image: resolve_image,
+                    subresource_range,
+                    ..Default::default()
+                });
+            }
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
         }
-        .unwrap();
 
-        let descriptor_sets = {
-            unsafe {
-                vk_backend.device.allocate_descriptor_sets(
-                    &vk::DescriptorSetAllocateInfo::builder()
-                        .descriptor_pool(descriptor_pool)
-                        .set_layouts(&vec![vk_backend.descriptor_set_layout; image_views.len()]),
+        device.end_command_buffer(command_buffer)?;
+        device.queue_submit(
+            self.vk_backend.graphics_queue,
+            &[vk::SubmitInfo::builder()
+                .command_buffers(std::slice::from_ref(&command_buffer))
+                .build()],
+            vk::Fence::null(),
+        )?;
+        device.queue_wait_idle(self.vk_backend.graphics_queue)
+    }
+
+    /// Records blitting every level from `base_level` up into runtime image `index`,
+    /// scaling them to the runtime's size
+    unsafe fn record_blits(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        index: usize,
+        base_level: u32,
+        flip: bool,
+    ) {
+        let image_info = &self.image_info;
+        let regions = (base_level..image_info.mip_count)
+            .map(|level| {
+                let (width, height) = mip_extent(image_info, level);
+                let runtime_width = (self.runtime_extent.width >> level).max(1) as i32;
+                let runtime_height = (self.runtime_extent.height >> level).max(1) as i32;
+                let subresource = vk::ImageSubresourceLayers {
+                    aspect_mask: image_info.format.vk_aspects(),
+                    mip_level: level,
+                    base_array_layer: 0,
+                    layer_count: image_info.array_layers(),
+                };
+                vk::ImageBlit {
+                    src_subresource: subresource,
+                    src_offsets: [
+                        vk::Offset3D { x: 0, y: 0, z: 0 },
+                        vk::Offset3D {
+                            x: width as i32,
+                            y: height as i32,
+                            z: 1,
+                        },
+                    ],
+                    dst_subresource: subresource,
+                    dst_offsets: [
+                        vk::Offset3D {
+                            x: 0,
+                            y: if flip { runtime_height } else { 0 },
+                            z: 0,
+                        },
+                        vk::Offset3D {
+                            x: runtime_width,
+                            y: if flip { 0 } else { runtime_height },
+                            z: 1,
+                        },
+                    ],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: image_info.format.vk_aspects(),
+            base_mip_level: base_level,
+            level_count: image_info.mip_count - base_level,
+            base_array_layer: 0,
+            layer_count: image_info.array_layers(),
+        };
+        with_runtime_levels_as_transfer_dst(
+            &self.vk_backend,
+            command_buffer,
+            self.runtime_images[index],
+            subresource_range,
+            || {
+                self.vk_backend.device.cmd_blit_image(
+                    command_buffer,
+                    self.images[index],
+                    self.interop_layout,
+                    self.runtime_images[index],
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                    self.filter,
                 )
-            }
+            },
+        );
+    }
+
+    /// Waits for every submitted copy, held images have nothing running and their fences
+    /// won't be signalled before they are queued
+    fn wait_submitted(&self, recording: &Recording) -> VkResult<()> {
+        let fences = self
+            .fences
+            .iter()
+            .zip(&recording.held)
+            .filter_map(|(&fence, &held)| (!held).then_some(fence))
+            .collect::<Vec<_>>();
+        if fences.is_empty() {
+            return Ok(());
+        }
+        unsafe {
+            self.vk_backend
+                .device
+                .wait_for_fences(&fences, true, u64::MAX)
         }
-        .unwrap();
+    }
 
-        for (&image_view, &set) in image_views.iter().zip(descriptor_sets.iter()) {
-            let image_info = vk::DescriptorImageInfo {
-                sampler: vk_backend.nearest_sampler,
-                image_view,
-                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            };
-            let descriptor_write = vk::WriteDescriptorSet::builder()
-                .dst_set(set)
-                .dst_binding(0)
-                .dst_array_element(0)
-                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .image_info(std::slice::from_ref(&image_info));
+    /// Records every command buffer again, replacing the draw resources
+    ///
+    /// None of the command buffers may be pending.
+    fn record(&self, recording: &mut Recording, alpha: AlphaConversion) -> VkResult<()> {
+        let vk_backend = &self.vk_backend;
+        let image_info = &self.image_info;
 
-            unsafe {
-                vk_backend
-                    .device
-                    .update_descriptor_sets(&[*descriptor_write], &[]);
-            }
+        if let Some(draw) = recording.draw.take() {
+            unsafe { draw.destroy(&vk_backend.device) };
+        }
+        if self.mode == CopyMode::Draw {
+            recording.draw = Some(DrawResources::new(self, alpha)?);
         }
 
-        for (i, &command_buffer) in command_buffers.iter().enumerate() {
-            let framebuffer = framebuffers[i];
-            let set = descriptor_sets[i];
+        for (i, &command_buffer) in self.command_buffers.iter().enumerate() {
             unsafe {
                 vk_backend
                     .device
-                    .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
-                    .unwrap();
+                    .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())?;
                 //On my Manjaro Linux install with proprietary Nvidia drivers this causes the interop image to be entirely black
                 //This issue has not been tested on any other machines so its disabled by default
                 // #[cfg(windows)]
@@ -268,123 +584,673 @@ impl SwapchainBackendVulkan {
                 //         ..Default::default()
                 //     }],
                 // );
-                vk_backend.device.cmd_begin_render_pass(
-                    command_buffer,
-                    &vk::RenderPassBeginInfo::builder()
-                        .render_pass(render_pass)
-                        .framebuffer(framebuffer)
-                        .render_area(vk::Rect2D {
-                            offset: vk::Offset2D { x: 0, y: 0 },
-                            extent: vk::Extent2D {
-                                width: image_info.width,
-                                height: image_info.height,
-                            },
-                        }),
-                    vk::SubpassContents::INLINE,
-                );
-                vk_backend.device.cmd_bind_pipeline(
-                    command_buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    pipeline,
-                );
-                vk_backend.device.cmd_bind_descriptor_sets(
-                    command_buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    pipeline_layout,
-                    0,
-                    std::slice::from_ref(&set),
-                    &[],
+                match self.mode {
+                    CopyMode::Draw => {
+                        if image_info.sample_count > 1 {
+                            record_resolve(
+                                vk_backend,
+                                command_buffer,
+                                self.images[i],
+                                self.sampled_images[i],
+                                image_info,
+                            );
+                        }
+                        recording.draw.as_ref().unwrap().record(
+                            vk_backend,
+                            command_buffer,
+                            i,
+                            image_info.array_layers(),
+                        );
+                        if image_info.mip_count > 1 {
+                            self.record_blits(command_buffer, i, 1, true);
+                        }
+                    }
+                    CopyMode::Blit { flip } => self.record_blits(command_buffer, i, 0, flip),
+                    CopyMode::Copy => record_copy(
+                        vk_backend,
+                        command_buffer,
+                        self.images[i],
+                        self.runtime_images[i],
+                        image_info,
+                    ),
+                }
+                vk_backend.device.end_command_buffer(command_buffer)?;
+            }
+        }
+        recording.alpha = alpha;
+        Ok(())
+    }
+}
+
+impl DrawResources {
+    fn new(swapchain: &SwapchainBackendVulkan, alpha: AlphaConversion) -> VkResult<Self> {
+        let vk_backend = &swapchain.vk_backend;
+        let image_info = &swapchain.image_info;
+        let runtime_format = swapchain.runtime_format;
+        let tone_mapping = swapchain.tone_mapping;
+
+        //Tone mapping and alpha conversion both go through the conversion shader
+        let tone_mapped = image_info.format.needs_tone_mapping(runtime_format);
+        let (views, convert) = if tone_mapped || !alpha.is_empty() {
+            let views = ViewFormats::converted(image_info.format, runtime_format);
+            let constants = convert_constants(
+                tone_mapped.then_some(tone_mapping),
+                views,
+                runtime_format,
+                alpha,
+            );
+            (views, Some(constants))
+        } else {
+            (ViewFormats::new(image_info.format, runtime_format), None)
+        };
+
+        let pipeline = vk_backend.pipeline(
+            views.rendered.to_vk().unwrap(),
+            vk::SampleCountFlags::TYPE_1,
+            convert.is_some(),
+        )?;
+
+        let mut draw = Self {
+            pipeline,
+            extent: swapchain.runtime_extent,
+            image_views: Vec::with_capacity(swapchain.sampled_images.len()),
+            runtime_image_views: Vec::with_capacity(swapchain.runtime_images.len()),
+            framebuffers: Vec::with_capacity(swapchain.runtime_images.len()),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_sets: Vec::new(),
+            convert,
+        };
+        //Whatever was created before a failure is destroyed again
+        if let Err(result) = draw.create(swapchain, views) {
+            unsafe { draw.destroy(&vk_backend.device) };
+            return Err(result);
+        }
+        Ok(draw)
+    }
+
+    fn create(&mut self, swapchain: &SwapchainBackendVulkan, views: ViewFormats) -> VkResult<()> {
+        let vk_backend = &swapchain.vk_backend;
+        let layers = swapchain.image_info.array_layers();
+        let sampled_layout = if swapchain.image_info.sample_count > 1 {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        } else {
+            swapchain.interop_layout
+        };
+        let sampler = match swapchain.filter {
+            vk::Filter::LINEAR => vk_backend.linear_sampler,
+            _ => vk_backend.nearest_sampler,
+        };
+        let convert = self.convert.is_some();
+        let descriptor_set_layout = if convert {
+            vk_backend.convert_descriptor_set_layout
+        } else {
+            vk_backend.descriptor_set_layout
+        };
+
+        for &image in &swapchain.sampled_images {
+            self.image_views.push(vk_backend.create_image_view(
+                image,
+                views.sampled.to_vk().unwrap(),
+                layers,
+            )?);
+        }
+
+        for &image in &swapchain.runtime_images {
+            self.runtime_image_views.push(vk_backend.create_image_view(
+                image,
+                views.rendered.to_vk().unwrap(),
+                layers,
+            )?);
+        }
+
+        for image_view in &self.runtime_image_views {
+            let create_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.pipeline.render_pass)
+                .attachments(std::slice::from_ref(image_view))
+                .width(self.extent.width)
+                .height(self.extent.height)
+                .layers(layers);
+            self.framebuffers
+                .push(unsafe { vk_backend.device.create_framebuffer(&create_info, None) }?);
+        }
+
+        self.descriptor_pool = unsafe {
+            let descriptor_types: &[_] = if convert {
+                &[
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                    vk::DescriptorType::SAMPLER,
+                ]
+            } else {
+                &[vk::DescriptorType::COMBINED_IMAGE_SAMPLER]
+            };
+            let descriptor_sizes = descriptor_types
+                .iter()
+                .map(|&ty| vk::DescriptorPoolSize {
+                    ty,
+                    descriptor_count: self.image_views.len() as u32,
+                })
+                .collect::<Vec<_>>();
+            let create_info = vk::DescriptorPoolCreateInfo::builder()
+                .pool_sizes(&descriptor_sizes)
+                .max_sets(self.image_views.len() as u32);
+
+            vk_backend.device.create_descriptor_pool(&create_info, None)
+        }?;
+
+        //The sets are freed with the pool
+        self.descriptor_sets = unsafe {
+            vk_backend.device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(self.descriptor_pool)
+                    .set_layouts(&vec![descriptor_set_layout; self.image_views.len()]),
+            )
+        }?;
+
+        for (&image_view, &set) in self.image_views.iter().zip(self.descriptor_sets.iter()) {
+            //The conversion layout takes the sampler at its own binding
+            let descriptor_type = if convert {
+                vk::DescriptorType::SAMPLED_IMAGE
+            } else {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            };
+            let image_info = vk::DescriptorImageInfo {
+                sampler,
+                image_view,
+                image_layout: sampled_layout,
+            };
+            let sampler_info = vk::DescriptorImageInfo {
+                sampler,
+                ..Default::default()
+            };
+            let mut descriptor_writes = vec![*vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(descriptor_type)
+                .image_info(std::slice::from_ref(&image_info))];
+            if convert {
+                descriptor_writes.push(
+                    *vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(1)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::SAMPLER)
+                        .image_info(std::slice::from_ref(&sampler_info)),
                 );
+            }
+
+            unsafe {
                 vk_backend
                     .device
-                    .cmd_draw(command_buffer, 3, image_info.layers, 0, 0);
-                vk_backend.device.cmd_end_render_pass(command_buffer);
-                vk_backend
-                    .device
-                    .end_command_buffer(command_buffer)
-                    .unwrap();
+                    .update_descriptor_sets(&descriptor_writes, &[]);
             }
         }
 
-        Self {
-            vk_backend,
-            images,
-            memory,
-            runtime_images,
-            pipeline_layout,
-            render_pass,
-            pipeline,
-            image_views,
-            runtime_image_views,
-            framebuffers,
-            command_buffers,
-            descriptor_pool,
+        Ok(())
+    }
+
+    unsafe fn record(
+        &self,
+        vk_backend: &VkBackend,
+        command_buffer: vk::CommandBuffer,
+        index: usize,
+        layers: u32,
+    ) {
+        let device = &vk_backend.device;
+        let area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &vk::RenderPassBeginInfo::builder()
+                .render_pass(self.pipeline.render_pass)
+                .framebuffer(self.framebuffers[index])
+                .render_area(area),
+            vk::SubpassContents::INLINE,
+        );
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.pipeline,
+        );
+        device.cmd_set_viewport(
+            command_buffer,
+            0,
+            &[vk::Viewport {
+                x: 0.,
+                y: 0.,
+                width: self.extent.width as f32,
+                height: self.extent.height as f32,
+                min_depth: 0.,
+                max_depth: 1.,
+            }],
+        );
+        device.cmd_set_scissor(command_buffer, 0, &[area]);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline.layout,
+            0,
+            std::slice::from_ref(&self.descriptor_sets[index]),
+            &[],
+        );
+        if let Some(constants) = &self.convert {
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                constants.as_bytes(),
+            );
+        }
+        device.cmd_draw(command_buffer, 3, layers, 0, 0);
+        device.cmd_end_render_pass(command_buffer);
+    }
+
+    unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        for &framebuffer in &self.framebuffers {
+            device.destroy_framebuffer(framebuffer, None)
+        }
+        for &view in &self.image_views {
+            device.destroy_image_view(view, None)
+        }
+        for &view in &self.runtime_image_views {
+            device.destroy_image_view(view, None)
         }
     }
 }
 
 impl SwapchainBackend for SwapchainBackendVulkan {
-    fn get_external_memory_handles(&self) -> Vec<(graphics_interop::InteropHandle, u64)> {
+    fn get_external_memory_handles(
+        &self,
+    ) -> openxr::Result<Vec<(graphics_interop::InteropHandle, u64)>> {
         self.memory
             .iter()
             .map(|&(mem, size)| {
-                (
-                    self.vk_backend
-                        .interop
-                        .get_external_memory_handle(mem)
-                        .unwrap(),
-                    size,
-                )
+                self.vk_backend
+                    .interop
+                    .get_external_memory_handle(mem)
+                    .map(|handle| (handle, size))
+            })
+            .collect::<VkResult<_>>()
+            .map_err(|result| {
+                error!("Failed to export the swapchain's memory: {}", result);
+                xr::Result::ERROR_RUNTIME_FAILURE
             })
-            .collect()
     }
 
-    fn release_image(&self, index: usize) {
+    fn get_external_semaphore_handles(
+        &self,
+    ) -> openxr::Result<Vec<graphics_interop::InteropHandle>> {
+        self.semaphores
+            .iter()
+            .map(|&semaphore| {
+                self.vk_backend
+                    .interop
+                    .get_external_semaphore_handle(semaphore)
+            })
+            .collect::<VkResult<_>>()
+            .map_err(|result| {
+                error!("Failed to export the swapchain's semaphores: {}", result);
+                xr::Result::ERROR_RUNTIME_FAILURE
+            })
+    }
+
+    fn drop_semaphores(&mut self) {
+        for semaphore in self.semaphores.drain(..) {
+            unsafe { self.vk_backend.device.destroy_semaphore(semaphore, None) };
+        }
+    }
+
+    fn release_image(&self, index: usize) -> openxr::Result<()> {
+        let _recording = self.recording.lock().unwrap();
+        let device = &self.vk_backend.device;
+        //Without semaphores OpenGL has already finished before releasing
+        let wait_semaphores = self.semaphores.get(index).map_or(&[][..], slice::from_ref);
+        let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+        //The runtime waits for work on its queue itself, so the copy only has to be submitted
+        //before the runtime image is released
+        //The image's previous copy may still be running if the application didn't wait on it
         unsafe {
+            device
+                .wait_for_fences(&self.fences[index..=index], true, u64::MAX)
+                .and_then(|()| device.reset_fences(&self.fences[index..=index]))
+        }
+        .map_err(|result| {
+            error!("Failed to wait for the copy of image {}: {}", index, result);
+            xr::Result::ERROR_RUNTIME_FAILURE
+        })?;
+        unsafe {
+            device.queue_submit(
+                self.vk_backend.graphics_queue,
+                slice::from_ref(
+                    &vk::SubmitInfo::builder()
+                        .wait_semaphores(wait_semaphores)
+                        .wait_dst_stage_mask(&wait_stages[..wait_semaphores.len()])
+                        .command_buffers(slice::from_ref(&self.command_buffers[index])),
+                ),
+                self.fences[index],
+            )
+        }
+        .map_err(|result| {
+            error!("Failed to submit the copy of image {}: {}", index, result);
+            self.vk_backend.skip_copies(&[QueuedCopy {
+                wait_semaphore: self.semaphores.get(index).copied(),
+                command_buffer: self.command_buffers[index],
+                fence: self.fences[index],
+            }]);
+            xr::Result::ERROR_RUNTIME_FAILURE
+        })
+    }
+
+    fn hold_release(&self, index: usize) -> openxr::Result<()> {
+        let mut recording = self.recording.lock().unwrap();
+        let device = &self.vk_backend.device;
+        unsafe {
+            device
+                .wait_for_fences(&self.fences[index..=index], true, u64::MAX)
+                .and_then(|()| device.reset_fences(&self.fences[index..=index]))
+        }
+        .map_err(|result| {
+            error!("Failed to wait for the copy of image {}: {}", index, result);
+            xr::Result::ERROR_RUNTIME_FAILURE
+        })?;
+        recording.held[index] = true;
+        Ok(())
+    }
+
+    fn queue_release(&self, index: usize) {
+        self.recording.lock().unwrap().held[index] = false;
+        self.vk_backend
+            .queued_copies
+            .lock()
+            .unwrap()
+            .push(QueuedCopy {
+                //Without semaphores OpenGL has already finished before releasing
+                wait_semaphore: self.semaphores.get(index).copied(),
+                command_buffer: self.command_buffers[index],
+                fence: self.fences[index],
+            });
+    }
+
+    fn wait_image(&self, index: usize, timeout: u64) -> bool {
+        //Its copy isn't even submitted yet
+        if self.recording.lock().unwrap().held[index] {
+            return false;
+        }
+        match unsafe {
             self.vk_backend
                 .device
-                .queue_submit(
-                    self.vk_backend.graphics_queue,
-                    slice::from_ref(
-                        &vk::SubmitInfo::builder()
-                            .command_buffers(slice::from_ref(&self.command_buffers[index])),
-                    ),
-                    vk::Fence::null(),
-                )
-                .unwrap();
-            self.vk_backend
-                .device
-                .queue_wait_idle(self.vk_backend.graphics_queue)
-                .unwrap();
+                .wait_for_fences(&self.fences[index..=index], true, timeout)
+        } {
+            Ok(()) => true,
+            Err(vk::Result::TIMEOUT) => false,
+            Err(result) => {
+                error!("Failed to wait for the copy of image {}: {}", index, result);
+                true
+            }
         }
     }
 
+    fn set_alpha_conversion(&self, conversion: AlphaConversion) -> bool {
+        //Only the draw changes colors, levels above 0 are blitted as they are
+        if self.mode != CopyMode::Draw || self.image_info.mip_count > 1 {
+            return conversion.is_empty();
+        }
+        let mut recording = self.recording.lock().unwrap();
+        if recording.alpha != conversion {
+            //The old draw resources may still be in use
+            if let Err(result) = self.wait_submitted(&recording) {
+                error!("Failed to wait for the copies: {}", result);
+                return false;
+            }
+            if let Err(result) = self.record(&mut recording, conversion) {
+                error!("Failed to record the copies: {}", result);
+                //The command buffers must not be submitted half recorded
+                let previous = recording.alpha;
+                if let Err(result) = self.record(&mut recording, previous) {
+                    error!("Failed to record the copies again: {}", result);
+                }
+                return false;
+            }
+        }
+        true
+    }
+
     fn destroy(&self) {
+        if let Err(result) = self.wait_submitted(&self.recording.lock().unwrap()) {
+            error!("Failed to wait for the copies: {}", result);
+        }
         unsafe {
             let device = &self.vk_backend.device;
+            if !self.command_buffers.is_empty() {
+                device
+                    .free_command_buffers(self.vk_backend.command_pool, &self.command_buffers[..]);
+            }
+            if let Some(draw) = self.recording.lock().unwrap().draw.take() {
+                draw.destroy(device);
+            }
+            //The runtime images are owned by the runtime swapchain
             for &image in &self.images {
                 device.destroy_image(image, None)
             }
             for &(mem, _) in &self.memory {
                 device.free_memory(mem, None);
             }
-            for &image in &self.runtime_images {
-                device.destroy_image(image, None)
-            }
-            device.destroy_pipeline_layout(self.pipeline_layout, None);
-            device.destroy_pipeline(self.pipeline, None);
-            device.destroy_render_pass(self.render_pass, None);
-            for &view in &self.image_views {
-                device.destroy_image_view(view, None)
+            for &(image, mem) in &self.resolve_images {
+                device.destroy_image(image, None);
+                device.free_memory(mem, None);
             }
-            for &view in &self.runtime_image_views {
-                device.destroy_image_view(view, None)
+            for &semaphore in &self.semaphores {
+                device.destroy_semaphore(semaphore, None);
             }
-            for &framebuffer in &self.framebuffers {
-                device.destroy_framebuffer(framebuffer, None)
+            for &fence in &self.fences {
+                device.destroy_fence(fence, None);
             }
-            device.free_command_buffers(self.vk_backend.command_pool, &self.command_buffers[..]);
-            device.destroy_descriptor_pool(self.descriptor_pool, None);
         }
     }
 }
+
+/// Records resolving a multisampled interop image into the single sample image the copy reads
+unsafe fn record_resolve(
+    vk_backend: &VkBackend,
+    command_buffer: vk::CommandBuffer,
+    multisampled: vk::Image,
+    resolved: vk::Image,
+    image_info: &graphics_interop::ImageCreateInfo,
+) {
+    let device = &vk_backend.device;
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        level_count: 1,
+        layer_count: image_info.array_layers(),
+        ..Default::default()
+    };
+    let subresource_layers = vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: 0,
+        base_array_layer: 0,
+        layer_count: image_info.array_layers(),
+    };
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::SHADER_READ,
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            old_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            image: resolved,
+            subresource_range,
+            ..Default::default()
+        }],
+    );
+    device.cmd_resolve_image(
+        command_buffer,
+        multisampled,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        resolved,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[vk::ImageResolve {
+            src_subresource: subresource_layers,
+            src_offset: vk::Offset3D::default(),
+            dst_subresource: subresource_layers,
+            dst_offset: vk::Offset3D::default(),
+            extent: vk::Extent3D {
+                width: image_info.width,
+                height: image_info.height,
+                depth: 1,
+            },
+        }],
+    );
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            image: resolved,
+            subresource_range,
+            ..Default::default()
+        }],
+    );
+}
+
+fn mip_extent(image_info: &graphics_interop::ImageCreateInfo, level: u32) -> (u32, u32) {
+    (
+        (image_info.width >> level).max(1),
+        (image_info.height >> level).max(1),
+    )
+}
+
+/// Transitions the given levels of a runtime image for a transfer and back again around `record`
+unsafe fn with_runtime_levels_as_transfer_dst(
+    vk_backend: &VkBackend,
+    command_buffer: vk::CommandBuffer,
+    runtime_image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+    record: impl FnOnce(),
+) {
+    //The runtime expects its images back as attachments
+    let (stage, access, layout) = if subresource_range
+        .aspect_mask
+        .contains(vk::ImageAspectFlags::COLOR)
+    {
+        (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )
+    } else {
+        (
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        )
+    };
+
+    let device = &vk_backend.device;
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        stage,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[vk::ImageMemoryBarrier {
+            src_access_mask: access,
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            image: runtime_image,
+            subresource_range,
+            ..Default::default()
+        }],
+    );
+    record();
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: layout,
+            image: runtime_image,
+            subresource_range,
+            ..Default::default()
+        }],
+    );
+}
+
+/// Records copying every level and layer of the interop image as is
+unsafe fn record_copy(
+    vk_backend: &VkBackend,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    runtime_image: vk::Image,
+    image_info: &graphics_interop::ImageCreateInfo,
+) {
+    let regions = (0..image_info.mip_count)
+        .map(|level| {
+            let (width, height) = mip_extent(image_info, level);
+            let subresource = vk::ImageSubresourceLayers {
+                aspect_mask: image_info.format.vk_aspects(),
+                mip_level: level,
+                base_array_layer: 0,
+                layer_count: image_info.array_layers(),
+            };
+            vk::ImageCopy {
+                src_subresource: subresource,
+                src_offset: vk::Offset3D::default(),
+                dst_subresource: subresource,
+                dst_offset: vk::Offset3D::default(),
+                extent: vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: image_info.format.vk_aspects(),
+        base_mip_level: 0,
+        level_count: image_info.mip_count,
+        base_array_layer: 0,
+        layer_count: image_info.array_layers(),
+    };
+    with_runtime_levels_as_transfer_dst(
+        vk_backend,
+        command_buffer,
+        runtime_image,
+        subresource_range,
+        || {
+            vk_backend.device.cmd_copy_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                runtime_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            )
+        },
+    );
+}
@@ -1,14 +1,22 @@
-use std::sync::Arc;
+use std::{
+    any::Any,
+    ffi::CStr,
+    os::raw::c_char,
+    sync::{mpsc, Arc},
+    time::Duration,
+};
 
 use ash::vk::Handle;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use openxr::{
     sys::{self as xr, pfn},
     Result,
 };
 
 use crate::{
-    graphics::{opengl::frontend::OpenGLFrontend, vulkan},
+    graphics::{opengl::frontend::OpenGLFrontend, vulkan, vulkan_backend::ToneMapping},
+    quirks::{RuntimeQuirks, EMULATABLE_EXTENSIONS},
+    settings::Settings,
     wrappers::{
         instance::{GraphicsEnableFlags, InstanceWrapper, SystemMeta},
         session::{SessionGraphics, SessionWrapper},
@@ -22,14 +30,140 @@ pub(super) unsafe fn get_instance_interceptors(name: &str) -> Option<pfn::VoidFu
     use xr::pfn::*;
     Some(match name {
         "xrGetSystem" => transmute(xr_get_system as GetSystem),
+        "xrEnumerateViewConfigurationViews" => {
+            transmute(xr_enumerate_view_configuration_views as EnumerateViewConfigurationViews)
+        }
         "xrGetOpenGLGraphicsRequirementsKHR" => {
             transmute(xr_get_opengl_graphics_requirements_khr as GetOpenGLGraphicsRequirementsKHR)
         }
         "xrCreateSession" => transmute(xr_create_session as CreateSession),
+        "xrDestroyInstance" => transmute(xr_destroy_instance as DestroyInstance),
+        _ => return get_null_instance_interceptors(name),
+    })
+}
+
+/// Interceptors for functions which can be called without an instance
+pub(super) unsafe fn get_null_instance_interceptors(name: &str) -> Option<pfn::VoidFunction> {
+    use std::mem::transmute;
+    use xr::pfn::*;
+    Some(match name {
+        "xrEnumerateInstanceExtensionProperties" => transmute(
+            xr_enumerate_instance_extension_properties as EnumerateInstanceExtensionProperties,
+        ),
         _ => return None,
     })
 }
 
+unsafe extern "system" fn xr_enumerate_instance_extension_properties(
+    layer_name: *const c_char,
+    property_capacity_input: u32,
+    property_count_output: *mut u32,
+    properties: *mut xr::ExtensionProperties,
+) -> xr::Result {
+    std::panic::catch_unwind(|| {
+        enumerate_instance_extension_properties(
+            layer_name,
+            property_capacity_input,
+            property_count_output,
+            properties,
+        )
+    })
+    .map_or(xr::Result::ERROR_RUNTIME_FAILURE, |res| match res {
+        Ok(res) => res,
+        Err(res) => res,
+    })
+}
+
+unsafe extern "system" fn xr_destroy_instance(instance: xr::Instance) -> xr::Result {
+    instance.run_destroy(|instance| destroy_instance(instance))
+}
+
+/// Destroys the instance along with any sessions and swapchains the application leaked
+fn destroy_instance(instance: &InstanceWrapper) -> Result<xr::Result> {
+    let sessions = instance
+        .sessions
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect::<Vec<_>>();
+    if !sessions.is_empty() {
+        warn!(
+            "Instance {:?} destroyed with {} session(s) still alive: {}",
+            instance.handle,
+            sessions.len(),
+            sessions
+                .iter()
+                .map(|session| format!(
+                    "{:?} ({} swapchain(s))",
+                    session.handle,
+                    session.swapchains.len()
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    for session in sessions {
+        if let Err(result) = super::session::destroy_session(&session) {
+            error!(
+                "Failed to destroy leaked session {:?}: {}",
+                session.handle, result
+            );
+        }
+    }
+
+    let result = if instance.settings.instance.threaded_destroy {
+        destroy_runtime_instance_threaded(instance)
+    } else {
+        unsafe { (instance.inner.core.destroy_instance)(instance.handle) }.result()
+    };
+
+    xr::Instance::all_wrappers().remove(&instance.handle);
+    //The layers bellow may be unloaded with the last instance
+    if xr::Instance::all_wrappers().is_empty() {
+        if let Ok(mut next) = super::NEXT_GET_INSTANCE_PROC_ADDR.write() {
+            *next = None;
+        }
+    }
+    info!("Instance destroyed: {:?}", instance.handle);
+
+    result
+}
+
+/// Forwards xrDestroyInstance on a worker thread and gives up waiting after the configured timeout
+///
+/// A hung worker is left behind, it is detached so it does not keep the process alive
+fn destroy_runtime_instance_threaded(instance: &InstanceWrapper) -> Result<xr::Result> {
+    let handle = instance.handle;
+    let destroy_instance = instance.inner.core.destroy_instance;
+    let timeout = Duration::from_millis(instance.settings.instance.destroy_timeout_ms);
+
+    let (sender, receiver) = mpsc::channel();
+    let spawned = std::thread::Builder::new()
+        .name("xrDestroyInstance".to_string())
+        .spawn(move || {
+            let result = unsafe { destroy_instance(handle) };
+            //The layer may have stopped waiting already
+            let _ = sender.send(result);
+        });
+    if let Err(err) = spawned {
+        warn!(
+            "Failed to spawn xrDestroyInstance thread, destroying on the calling thread: {}",
+            err
+        );
+        return unsafe { destroy_instance(handle) }.result();
+    }
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result.result(),
+        Err(_) => {
+            error!(
+                "Runtime did not return from xrDestroyInstance within {}ms, returning to the application anyway",
+                timeout.as_millis()
+            );
+            Ok(xr::Result::SUCCESS)
+        }
+    }
+}
+
 unsafe extern "system" fn xr_get_system(
     instance: xr::Instance,
     get_info: *const xr::SystemGetInfo,
@@ -38,6 +172,43 @@ unsafe extern "system" fn xr_get_system(
     instance.run(|instance| get_system(instance, &*get_info, &mut *system_id))
 }
 
+unsafe extern "system" fn xr_enumerate_view_configuration_views(
+    instance: xr::Instance,
+    system_id: xr::SystemId,
+    view_configuration_type: xr::ViewConfigurationType,
+    view_capacity_input: u32,
+    view_count_output: *mut u32,
+    views: *mut xr::ViewConfigurationView,
+) -> xr::Result {
+    instance.run(|instance| {
+        let success = (instance.inner.core.enumerate_view_configuration_views)(
+            instance.handle,
+            system_id,
+            view_configuration_type,
+            view_capacity_input,
+            view_count_output,
+            views,
+        )
+        .result()?;
+
+        //Upscaled applications render at a fraction of the size, the copy scales them back up
+        if let Some(upscale) = instance.upscale() {
+            if view_capacity_input > 0 && !views.is_null() {
+                let count = (*view_count_output).min(view_capacity_input) as usize;
+                let downscale = |size: u32| ((size as f32 / upscale).round() as u32).max(1);
+                for view in std::slice::from_raw_parts_mut(views, count) {
+                    view.recommended_image_rect_width =
+                        downscale(view.recommended_image_rect_width);
+                    view.recommended_image_rect_height =
+                        downscale(view.recommended_image_rect_height);
+                }
+            }
+        }
+
+        Ok(success)
+    })
+}
+
 unsafe extern "system" fn xr_get_opengl_graphics_requirements_khr(
     instance: xr::Instance,
     system_id: xr::SystemId,
@@ -51,6 +222,19 @@ unsafe extern "system" fn xr_get_opengl_graphics_requirements_khr(
             None => return Err(xr::Result::ERROR_SYSTEM_INVALID),
         }
 
+        if !instance.opengl_compat() {
+            let mut function = None;
+            (instance.inner.core.get_instance_proc_addr)(
+                instance.handle,
+                "xrGetOpenGLGraphicsRequirementsKHR\0".as_ptr() as _,
+                &mut function,
+            )
+            .result()?;
+            let function: pfn::GetOpenGLGraphicsRequirementsKHR =
+                std::mem::transmute(function.ok_or(xr::Result::ERROR_FUNCTION_UNSUPPORTED)?);
+            return function(instance.handle, system_id, graphics_requirements).result();
+        }
+
         (*graphics_requirements).max_api_version_supported = openxr::Version::new(4, 6, 0);
         (*graphics_requirements).min_api_version_supported = openxr::Version::new(4, 5, 0);
 
@@ -66,6 +250,152 @@ unsafe extern "system" fn xr_create_session(
     instance.run(|instance| create_session(instance, &*create_info, &mut *session))
 }
 
+unsafe fn enumerate_instance_extension_properties(
+    layer_name: *const c_char,
+    property_capacity_input: u32,
+    property_count_output: *mut u32,
+    properties: *mut xr::ExtensionProperties,
+) -> Result<xr::Result> {
+    let next_enumerate = match super::next_get_instance_proc_addr() {
+        Some(next) => {
+            let mut function = None;
+            next(
+                xr::Instance::NULL,
+                "xrEnumerateInstanceExtensionProperties\0".as_ptr() as _,
+                &mut function,
+            )
+            .result()?;
+            function.map(|function| {
+                std::mem::transmute::<_, pfn::EnumerateInstanceExtensionProperties>(function)
+            })
+        }
+        None => None,
+    };
+
+    let is_this_layer = !layer_name.is_null()
+        && CStr::from_ptr(layer_name).to_bytes() == crate::LAYER_NAME.as_bytes();
+
+    if !layer_name.is_null() && !is_this_layer {
+        //Another layer's extensions are not ours to change
+        return match next_enumerate {
+            Some(next_enumerate) => next_enumerate(
+                layer_name,
+                property_capacity_input,
+                property_count_output,
+                properties,
+            )
+            .result(),
+            None => Err(xr::Result::ERROR_API_LAYER_NOT_PRESENT),
+        };
+    }
+
+    //Which runtime is bellow is only known once an instance exists
+    let quirks = RuntimeQuirks::unknown_runtime(&Settings::current().runtimes);
+
+    let emulated = EMULATABLE_EXTENSIONS
+        .iter()
+        .filter(|(name, _)| quirks.emulated_extensions.iter().any(|ext| ext == name))
+        .map(|&(name, version)| (name.to_owned(), version));
+
+    let extensions = if is_this_layer {
+        emulated.collect::<Vec<_>>()
+    } else {
+        let runtime_extensions = match next_enumerate {
+            Some(next_enumerate) => super::call_enumerate(
+                std::ptr::null::<c_char>(),
+                next_enumerate,
+                xr::ExtensionProperties {
+                    ty: xr::ExtensionProperties::TYPE,
+                    next: std::ptr::null_mut(),
+                    extension_name: [0; xr::MAX_EXTENSION_NAME_SIZE],
+                    extension_version: 0,
+                },
+            )?
+            .iter()
+            .map(|properties| {
+                (
+                    CStr::from_ptr(properties.extension_name.as_ptr())
+                        .to_string_lossy()
+                        .into_owned(),
+                    properties.extension_version,
+                )
+            })
+            .collect::<Vec<_>>(),
+            None => {
+                //Only the layer's own extensions are known without `instance.load_active_runtime`
+                debug!("Runtime extensions are unknown until an instance is created");
+                Vec::new()
+            }
+        };
+
+        //Emulation goes through the Vulkan backend so is only possible on top of Vulkan
+        let can_emulate = next_enumerate.is_none()
+            || runtime_extensions
+                .iter()
+                .any(|(name, _)| name == "XR_KHR_vulkan_enable2" || name == "XR_KHR_vulkan_enable");
+
+        let mut extensions = runtime_extensions
+            .into_iter()
+            .filter(|(name, _)| !quirks.hidden_extensions.contains(name))
+            .collect::<Vec<_>>();
+
+        if can_emulate {
+            for (name, version) in emulated {
+                if !extensions.iter().any(|(existing, _)| *existing == name) {
+                    extensions.push((name, version));
+                }
+            }
+        }
+
+        extensions
+    };
+
+    write_extension_properties(
+        property_capacity_input,
+        property_count_output,
+        properties,
+        &extensions,
+    )
+}
+
+unsafe fn write_extension_properties(
+    capacity: u32,
+    count_output: *mut u32,
+    out: *mut xr::ExtensionProperties,
+    extensions: &[(String, u32)],
+) -> Result<xr::Result> {
+    if count_output.is_null() {
+        return Err(xr::Result::ERROR_VALIDATION_FAILURE);
+    }
+    if capacity != 0 {
+        if (capacity as usize) < extensions.len() {
+            *count_output = extensions.len() as u32;
+            return Err(xr::Result::ERROR_SIZE_INSUFFICIENT);
+        }
+        if out.is_null() {
+            return Err(xr::Result::ERROR_VALIDATION_FAILURE);
+        }
+        let slice = std::slice::from_raw_parts_mut(out, extensions.len());
+        for (properties, (name, version)) in slice.iter_mut().zip(extensions) {
+            if properties.ty != xr::ExtensionProperties::TYPE {
+                return Err(xr::Result::ERROR_VALIDATION_FAILURE);
+            }
+            let len = name.len().min(xr::MAX_EXTENSION_NAME_SIZE - 1);
+            properties.extension_name = [0; xr::MAX_EXTENSION_NAME_SIZE];
+            for (dst, &src) in properties
+                .extension_name
+                .iter_mut()
+                .zip(&name.as_bytes()[..len])
+            {
+                *dst = src as c_char;
+            }
+            properties.extension_version = *version;
+        }
+    }
+    *count_output = extensions.len() as u32;
+    Ok(xr::Result::SUCCESS)
+}
+
 fn get_system(
     instance: &InstanceWrapper,
     get_info: &xr::SystemGetInfo,
@@ -96,71 +426,61 @@ fn create_session(
     create_info: &xr::SessionCreateInfo,
     session: &mut xr::Session,
 ) -> Result<xr::Result> {
-    let mut needs_compat = false;
+    let opengl_override = instance.opengl_compat();
 
-    let opengl_override = true;
+    //The graphics binding can be anywhere in the chain, everything else is forwarded untouched
+    let chain = unsafe { super::next_chain(create_info.next) };
+    let binding_index = chain.iter().position(|&next| {
+        matches!(
+            unsafe { (*next).ty },
+            xr::StructureType::GRAPHICS_BINDING_D3D11_KHR
+                | xr::StructureType::GRAPHICS_BINDING_D3D12_KHR
+                | xr::StructureType::GRAPHICS_BINDING_EGL_MNDX
+                | xr::StructureType::GRAPHICS_BINDING_OPENGL_WIN32_KHR
+                | xr::StructureType::GRAPHICS_BINDING_OPENGL_XLIB_KHR
+                | xr::StructureType::GRAPHICS_BINDING_OPENGL_XCB_KHR
+                | xr::StructureType::GRAPHICS_BINDING_OPENGL_WAYLAND_KHR
+                | xr::StructureType::GRAPHICS_BINDING_OPENGL_ES_ANDROID_KHR
+                | xr::StructureType::GRAPHICS_BINDING_VULKAN_KHR
+        )
+    });
 
-    unsafe {
-        if !create_info.next.is_null() {
-            let next: *const xr::BaseInStructure = std::mem::transmute(create_info.next);
+    let needs_compat = match binding_index.map(|index| unsafe { (*chain[index]).ty }) {
+        Some(xr::StructureType::GRAPHICS_BINDING_EGL_MNDX)
+        | Some(xr::StructureType::GRAPHICS_BINDING_OPENGL_WIN32_KHR)
+        | Some(xr::StructureType::GRAPHICS_BINDING_OPENGL_XLIB_KHR)
+        | Some(xr::StructureType::GRAPHICS_BINDING_OPENGL_XCB_KHR)
+        | Some(xr::StructureType::GRAPHICS_BINDING_OPENGL_WAYLAND_KHR)
+        | Some(xr::StructureType::GRAPHICS_BINDING_OPENGL_ES_ANDROID_KHR) => opengl_override,
+        _ => false,
+    };
 
-            if !(*next).next.is_null() {
-                todo!();
-            }
+    let opengl_context = match binding_index {
+        Some(index) if needs_compat => unsafe { opengl_context(chain[index]) },
+        _ => None,
+    };
 
-            needs_compat = match (*next).ty {
-                xr::StructureType::GRAPHICS_BINDING_D3D11_KHR => false,
-                xr::StructureType::GRAPHICS_BINDING_D3D12_KHR => false,
-                xr::StructureType::GRAPHICS_BINDING_EGL_MNDX => opengl_override,
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_WIN32_KHR => opengl_override,
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_XLIB_KHR => opengl_override,
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_XCB_KHR => opengl_override,
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_WAYLAND_KHR => opengl_override,
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_ES_ANDROID_KHR => opengl_override,
-                xr::StructureType::GRAPHICS_BINDING_VULKAN_KHR => false,
-                _ => false,
-            };
-        }
-    }
+    let session_wrapper = if let Some(opengl_context) = opengl_context {
+        let binding_index = binding_index.unwrap();
+        let binding = chain[binding_index];
 
-    let session_wrapper = if needs_compat {
-        let opengl_context = unsafe {
-            use crate::graphics::opengl::*;
-            let next: *const xr::BaseInStructure = std::mem::transmute(create_info.next);
-            match (*next).ty {
-                xr::StructureType::GRAPHICS_BINDING_EGL_MNDX => todo!(),
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_WIN32_KHR => {
-                    #[cfg(windows)]
-                    {
-                        let binding =
-                            &*(create_info.next as *const xr::GraphicsBindingOpenGLWin32KHR);
-                        GLContext::Wgl(platform::windows::WGL::load(binding.h_dc, binding.h_glrc))
-                    }
-                    #[cfg(target_os = "linux")]
-                    todo!()
-                }
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_XLIB_KHR => {
-                    #[cfg(target_os = "linux")]
-                    {
-                        let binding =
-                            &*(create_info.next as *const xr::GraphicsBindingOpenGLXlibKHR);
-                        GLContext::X11(platform::linux::X11 {
-                            x_display: binding.x_display as _,
-                            visualid: binding.visualid,
-                            glx_fb_config: binding.glx_fb_config,
-                            glx_drawable: binding.glx_drawable,
-                            glx_context: binding.glx_context,
-                        })
-                    }
-                    #[cfg(windows)]
-                    todo!()
+        //The structs ahead of the binding are copied so they can point at the Vulkan binding
+        //instead, the application's chain is left as it is
+        let mut storage = Vec::<Box<dyn Any>>::new();
+        let mut copies = Vec::with_capacity(binding_index);
+        for &next in &chain[..binding_index] {
+            match unsafe { copy_session_struct(next, &mut storage) } {
+                Some(copy) => copies.push(copy),
+                //Its size is unknown so it can't be copied, nor can the chain be changed without it
+                None => {
+                    error!("Can't move the graphics binding past a {:?}", unsafe {
+                        (*next).ty
+                    });
+                    return Err(xr::Result::ERROR_FEATURE_UNSUPPORTED);
                 }
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_XCB_KHR => todo!(),
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_WAYLAND_KHR => todo!(),
-                xr::StructureType::GRAPHICS_BINDING_OPENGL_ES_ANDROID_KHR => todo!(),
-                _ => unreachable!(),
             }
-        };
+        }
+
         if !instance
             .systems
             .get(&create_info.system_id)
@@ -184,7 +504,7 @@ fn create_session(
 
         let vulkan = xr::GraphicsBindingVulkanKHR {
             ty: xr::GraphicsBindingVulkanKHR::TYPE,
-            next: std::ptr::null(),
+            next: unsafe { (*binding).next } as _,
             instance: vk_backend.instance.handle().as_raw() as _,
             physical_device: vk_backend.physical_device.as_raw() as _,
             device: vk_backend.device.handle().as_raw() as _,
@@ -192,32 +512,73 @@ fn create_session(
             queue_index: 0,
         };
 
+        //The Vulkan binding takes the place of the OpenGL one
+        let mut next = &vulkan as *const xr::GraphicsBindingVulkanKHR as *const xr::BaseInStructure;
+        for &copy in copies.iter().rev() {
+            unsafe { (*copy).next = next };
+            next = copy;
+        }
+
         let create_info2 = xr::SessionCreateInfo {
             ty: xr::SessionCreateInfo::TYPE,
-            next: &vulkan as *const _ as _,
-            create_flags: xr::SessionCreateFlags::EMPTY,
+            next: next as _,
+            create_flags: create_info.create_flags,
             system_id: create_info.system_id,
         };
 
         unsafe { (instance.inner.core.create_session)(instance.handle, &create_info2, session) }
             .result()?;
 
-        let swapchain_formats = unsafe {
+        let preferred_formats = instance.settings.swapchain.preferred_formats();
+        let blocked_formats = instance.settings.swapchain.blocked_formats();
+
+        let runtime_formats = unsafe {
             super::call_enumerate(*session, instance.inner.core.enumerate_swapchain_formats, 0)?
                 .iter()
                 .filter_map(|backend_format| {
-                    log::info!("b {:?}", backend_format);
                     let vulkan_format = ash::vk::Format::from_raw(*backend_format as i32);
-                    graphics_interop::ImageFormat::from_vk(vulkan_format)
-                        .and_then(|format| {
-                            log::info!("f{:?}", format);
-                            format.to_gl()
-                        })
-                        .map(|f| f as i64)
+                    let format = graphics_interop::ImageFormat::from_vk(vulkan_format);
+                    if format.is_none() {
+                        debug!("Runtime swapchain format {:?} is not known", vulkan_format);
+                    }
+                    format
                 })
+                .filter(|format| !instance.quirks.blocked_formats.contains(format))
                 .collect::<Vec<_>>()
         };
 
+        //Formats the copy converts into one the runtime has come after everything native
+        let converted = graphics_interop::ImageFormat::ALL
+            .into_iter()
+            .filter(|format| {
+                !runtime_formats.contains(format)
+                    && format
+                        .equivalents()
+                        .iter()
+                        .any(|equivalent| runtime_formats.contains(equivalent))
+            });
+        let mut swapchain_formats = runtime_formats
+            .iter()
+            .copied()
+            .chain(converted)
+            .filter(|format| format.to_gl().is_some() && !blocked_formats.contains(format))
+            .collect::<Vec<_>>();
+
+        //Stable sort so runtime order is kept for everything that isn't preferred
+        swapchain_formats.sort_by_key(|format| {
+            preferred_formats
+                .iter()
+                .position(|preferred| preferred == format)
+                .unwrap_or(preferred_formats.len())
+        });
+
+        debug!("Compat swapchain formats: {:?}", swapchain_formats);
+
+        let swapchain_formats = swapchain_formats
+            .into_iter()
+            .map(|format| format.to_gl().unwrap() as i64)
+            .collect();
+
         Arc::new(SessionWrapper {
             handle: *session,
             instance: Arc::downgrade(instance),
@@ -226,8 +587,17 @@ fn create_session(
                 frontend: Arc::new(OpenGLFrontend::load(opengl_context)),
                 backend: Arc::new(vk_backend),
                 swapchain_formats,
+                runtime_formats,
+                tone_mapping: ToneMapping {
+                    curve: instance.settings.swapchain.tone_curve,
+                    dither: instance.settings.swapchain.dither,
+                },
+                alpha_conversion: instance.settings.composition.converts_alpha(),
+                batch_copies: instance.settings.composition.batches_copies(),
+                upscale: instance.upscale(),
             },
             swapchains: Default::default(),
+            pending_releases: Default::default(),
         })
     } else {
         unsafe { (instance.inner.core.create_session)(instance.handle, create_info, session) }
@@ -238,6 +608,7 @@ fn create_session(
             inner: instance.inner.clone(),
             graphics: SessionGraphics::Direct,
             swapchains: Default::default(),
+            pending_releases: Default::default(),
         })
     };
 
@@ -248,3 +619,65 @@ fn create_session(
 
     Ok(xr::Result::SUCCESS)
 }
+
+/// The OpenGL context behind a graphics binding, `None` if the compat path can't use it
+unsafe fn opengl_context(
+    binding: *const xr::BaseInStructure,
+) -> Option<crate::graphics::opengl::GLContext> {
+    use crate::graphics::opengl::*;
+    match (*binding).ty {
+        #[cfg(windows)]
+        xr::StructureType::GRAPHICS_BINDING_OPENGL_WIN32_KHR => {
+            let binding = &*(binding as *const xr::GraphicsBindingOpenGLWin32KHR);
+            Some(GLContext::Wgl(platform::windows::WGL::load(
+                binding.h_dc,
+                binding.h_glrc,
+            )))
+        }
+        #[cfg(target_os = "linux")]
+        xr::StructureType::GRAPHICS_BINDING_OPENGL_XLIB_KHR => {
+            let binding = &*(binding as *const xr::GraphicsBindingOpenGLXlibKHR);
+            Some(GLContext::X11(platform::linux::X11 {
+                x_display: binding.x_display as _,
+                visualid: binding.visualid,
+                glx_fb_config: binding.glx_fb_config,
+                glx_drawable: binding.glx_drawable,
+                glx_context: binding.glx_context,
+            }))
+        }
+        ty => {
+            warn!(
+                "{:?} can't be run on Vulkan yet, leaving the session to the runtime",
+                ty
+            );
+            None
+        }
+    }
+}
+
+/// Copies a struct the layer knows from a session create info chain into `storage`,
+/// `None` for structs of unknown size
+unsafe fn copy_session_struct(
+    next: *const xr::BaseInStructure,
+    storage: &mut Vec<Box<dyn Any>>,
+) -> Option<*mut xr::BaseInStructure> {
+    unsafe fn copy<T: Copy + 'static>(
+        next: *const xr::BaseInStructure,
+        storage: &mut Vec<Box<dyn Any>>,
+    ) -> *mut xr::BaseInStructure {
+        let mut copy = Box::new(*(next as *const T));
+        let copy_ptr = copy.as_mut() as *mut T as *mut xr::BaseInStructure;
+        storage.push(copy);
+        copy_ptr
+    }
+
+    Some(match (*next).ty {
+        xr::StructureType::SESSION_CREATE_INFO_OVERLAY_EXTX => {
+            copy::<xr::SessionCreateInfoOverlayEXTX>(next, storage)
+        }
+        xr::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT => {
+            copy::<xr::DebugUtilsMessengerCreateInfoEXT>(next, storage)
+        }
+        _ => return None,
+    })
+}
@@ -2,16 +2,38 @@ pub mod instance;
 mod session;
 mod swapchain;
 
-use std::{ffi::CStr, os::raw::c_char};
+use std::{ffi::CStr, os::raw::c_char, sync::RwLock};
 
 use log::{error, trace, warn};
 use openxr::sys::{self as xr, pfn};
 use openxr::Result;
 
+use crate::settings::Settings;
 use crate::wrappers::instance::InstanceWrapper;
 use crate::wrappers::XrHandle;
 use crate::ToResult;
 
+/// The `xrGetInstanceProcAddr` of the layer bellow us, saved when an instance is created
+/// so functions which don't take an instance can still be forwarded
+pub(crate) static NEXT_GET_INSTANCE_PROC_ADDR: RwLock<Option<pfn::GetInstanceProcAddr>> =
+    RwLock::new(None);
+
+/// The next xrGetInstanceProcAddr, before any instance exists that is the active runtime's if
+/// `instance.load_active_runtime` is set
+pub(crate) fn next_get_instance_proc_addr() -> Option<pfn::GetInstanceProcAddr> {
+    NEXT_GET_INSTANCE_PROC_ADDR
+        .read()
+        .ok()
+        .and_then(|next| *next)
+        .or_else(|| {
+            if Settings::current().instance.load_active_runtime {
+                crate::active_runtime::get_instance_proc_addr()
+            } else {
+                None
+            }
+        })
+}
+
 pub(crate) unsafe extern "system" fn get_instance_proc_addr(
     instance: xr::Instance,
     name: *const c_char,
@@ -19,9 +41,36 @@ pub(crate) unsafe extern "system" fn get_instance_proc_addr(
 ) -> xr::Result {
     trace!("Get proc addr");
 
+    if instance == xr::Instance::NULL {
+        return std::panic::catch_unwind(|| null_instance_proc_addr(name, &mut *function))
+            .unwrap_or(xr::Result::ERROR_RUNTIME_FAILURE);
+    }
+
     instance.run(|instance| instance_proc_addr(instance, name, &mut *function))
 }
 
+unsafe fn null_instance_proc_addr(
+    name: *const c_char,
+    function: &mut Option<pfn::VoidFunction>,
+) -> xr::Result {
+    if let Ok(name_str) = CStr::from_ptr(name).to_str() {
+        trace!("get_instance_proc_addr(XR_NULL_HANDLE, {})", name_str);
+
+        *function = instance::get_null_instance_interceptors(name_str);
+        if function.is_some() {
+            return xr::Result::SUCCESS;
+        }
+    }
+
+    match next_get_instance_proc_addr() {
+        Some(next) => next(xr::Instance::NULL, name, function),
+        None => {
+            *function = None;
+            xr::Result::ERROR_HANDLE_INVALID
+        }
+    }
+}
+
 const INTERCEPTORS: [unsafe fn(&str) -> Option<pfn::VoidFunction>; 3] = [
     instance::get_instance_interceptors,
     session::get_session_interceptors,
@@ -45,7 +94,12 @@ fn instance_proc_addr(
 
     trace!("get_instance_proc_addr({})", name_str);
 
-    (*function) = unsafe { INTERCEPTORS.iter().find_map(|f| f(name_str)) };
+    //xrEndFrame is only needed to rewrite layers and submit batched copies
+    (*function) = if name_str == "xrEndFrame" && !instance.intercepts_end_frame() {
+        None
+    } else {
+        unsafe { INTERCEPTORS.iter().find_map(|f| f(name_str)) }
+    };
 
     if function.is_some() {
         Ok(xr::Result::SUCCESS)
@@ -109,3 +163,14 @@ pub unsafe fn call_enumerate<H: Copy, T: Copy>(
         })?;
     Ok(vec)
 }
+
+/// Collects every struct in a `next` chain, in order
+pub(crate) unsafe fn next_chain(next: *const std::ffi::c_void) -> Vec<*const xr::BaseInStructure> {
+    let mut chain = Vec::new();
+    let mut current = next as *const xr::BaseInStructure;
+    while !current.is_null() {
+        chain.push(current);
+        current = (*current).next;
+    }
+    chain
+}
@@ -1,11 +1,16 @@
-use std::sync::Arc;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::sync::{atomic::AtomicBool, Arc};
 
-use graphics_interop::ImageFormat;
-use log::info;
+use ash::vk;
+use graphics_interop::{ImageFormat, ImageUsage};
+use log::{debug, error, info, warn};
 use openxr::sys as xr;
 use openxr::Result;
 
-use crate::graphics::vulkan_backend::SwapchainBackendVulkan;
+use crate::graphics::vulkan::VkBackend;
+use crate::graphics::vulkan_backend::{CopyMode, RuntimeTarget, SwapchainBackendVulkan};
+use crate::wrappers::swapchain::AlphaConversion;
 use crate::wrappers::swapchain::SwapchainBackend;
 use crate::wrappers::swapchain::SwapchainGraphics;
 use crate::wrappers::swapchain::SwapchainWrapper;
@@ -25,11 +30,55 @@ pub(super) unsafe fn get_session_interceptors(name: &str) -> Option<xr::pfn::Voi
             transmute(xr_enumerate_swapchain_formats as EnumerateSwapchainFormats)
         }
         "xrCreateSwapchain" => transmute(xr_create_swapchain as CreateSwapchain),
-        // "xrEndFrame" => transmute(xr_end_frame as EndFrame),
+        "xrDestroySession" => transmute(xr_destroy_session as DestroySession),
+        "xrEndFrame" => transmute(xr_end_frame as EndFrame),
         _ => return None,
     })
 }
 
+unsafe extern "system" fn xr_destroy_session(session: xr::Session) -> xr::Result {
+    session.run_destroy(|session| destroy_session(session))
+}
+
+/// Destroys the session along with any swapchains the application leaked
+pub(super) fn destroy_session(session: &SessionWrapper) -> Result<xr::Result> {
+    let swapchains = session
+        .swapchains
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect::<Vec<_>>();
+    if !swapchains.is_empty() {
+        warn!(
+            "Session {:?} destroyed with {} swapchain(s) still alive: {:?}",
+            session.handle,
+            swapchains.len(),
+            swapchains
+                .iter()
+                .map(|swapchain| swapchain.handle)
+                .collect::<Vec<_>>()
+        );
+    }
+    for swapchain in swapchains {
+        if let Err(result) = super::swapchain::destroy_swapchain(&swapchain) {
+            error!(
+                "Failed to destroy leaked swapchain {:?}: {}",
+                swapchain.handle, result
+            );
+        }
+    }
+
+    //The graphics binding has to outlive the runtime session so it is freed with the wrapper
+    let result = unsafe { (session.inner.core.destroy_session)(session.handle) }.result();
+
+    xr::Session::all_wrappers().remove(&session.handle);
+    if let Some(instance) = session.instance.upgrade() {
+        instance.sessions.remove(&session.handle);
+    }
+    info!("Session destroyed: {:?}", session.handle);
+
+    result
+}
+
 pub(crate) unsafe extern "system" fn xr_enumerate_swapchain_formats(
     session: xr::Session,
     format_capacity_input: u32,
@@ -73,35 +122,86 @@ fn create_swapchain(
     swapchain: &mut xr::Swapchain,
 ) -> Result<xr::Result> {
     let swapchain_wrapper = if let SessionGraphics::Compat {
-        frontend, backend, ..
+        frontend,
+        backend,
+        swapchain_formats,
+        runtime_formats,
+        tone_mapping,
+        alpha_conversion,
+        upscale,
+        ..
     } = &session.graphics
     {
-        //TODO SRGB
         let format = ImageFormat::from_gl(create_info.format as u32)
+            .filter(|_| swapchain_formats.contains(&create_info.format))
+            .ok_or_else(|| {
+                warn!(
+                    "Swapchain format {:#X} is not supported",
+                    create_info.format
+                );
+                xr::Result::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED
+            })?;
+        //Formats the runtime lacks are backed by one holding the same values, like BGRA for RGBA
+        let runtime_format = format
+            .equivalents()
+            .into_iter()
+            .find(|format| runtime_formats.contains(format))
             .ok_or(xr::Result::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED)?;
+        if runtime_format != format {
+            debug!(
+                "{:?} swapchain is backed by a {:?} runtime swapchain",
+                format, runtime_format
+            );
+        }
+
+        let mut interop_info = graphics_interop::ImageCreateInfo {
+            width: create_info.width,
+            height: create_info.height,
+            mip_count: create_info.mip_count,
+            sample_count: create_info.sample_count,
+            layers: create_info.array_size,
+            faces: create_info.face_count,
+            format,
+            usage: image_usage(create_info.usage_flags),
+        };
+        let mode = CopyMode::new(backend, &interop_info, runtime_format, *alpha_conversion);
+        debug!("{:?} swapchain is copied with {:?}", format, mode);
+        interop_info.usage |=
+            SwapchainBackendVulkan::interop_usage(&interop_info, runtime_format, mode);
+
+        validate_compat_create_info(create_info, &interop_info, runtime_format, mode, backend)?;
 
-        assert_eq!(create_info.sample_count, 1);
-        assert_eq!(create_info.face_count, 1);
-        assert_eq!(create_info.mip_count, 1);
-        assert!(create_info.array_size <= 32);
-        assert!(create_info.create_flags.is_empty());
-        assert!(create_info.next.is_null());
+        //Cubemaps aren't rendered at the recommended size and may be copied, which can't scale
+        let (runtime_width, runtime_height) = match upscale {
+            Some(upscale) if create_info.face_count == 1 => (
+                (create_info.width as f32 * upscale).round() as u32,
+                (create_info.height as f32 * upscale).round() as u32,
+            ),
+            _ => (create_info.width, create_info.height),
+        };
+        if runtime_width != create_info.width || runtime_height != create_info.height {
+            debug!(
+                "{}x{} swapchain is scaled up to {}x{}",
+                create_info.width, create_info.height, runtime_width, runtime_height
+            );
+        }
 
         let create_info2 = xr::SwapchainCreateInfo {
             ty: xr::SwapchainCreateInfo::TYPE,
-            next: std::ptr::null(),
-            create_flags: xr::SwapchainCreateFlags::EMPTY,
-            usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT,
-            format: format
+            next: create_info.next,
+            create_flags: create_info.create_flags,
+            usage_flags: create_info.usage_flags
+                | SwapchainBackendVulkan::runtime_usage(&interop_info, runtime_format, mode),
+            format: runtime_format
                 .to_vk()
                 .ok_or(xr::Result::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED)?
                 .as_raw() as i64,
             sample_count: 1,
-            width: create_info.width,
-            height: create_info.height,
-            face_count: 1,
+            width: runtime_width,
+            height: runtime_height,
+            face_count: create_info.face_count,
             array_size: create_info.array_size,
-            mip_count: 1,
+            mip_count: create_info.mip_count,
         };
 
         unsafe {
@@ -109,27 +209,56 @@ fn create_swapchain(
                 .result()?
         };
 
-        let interop_info = graphics_interop::ImageCreateInfo {
-            width: create_info.width,
-            height: create_info.height,
-            mip_count: create_info.mip_count,
-            sample_count: create_info.sample_count,
-            layers: create_info.array_size,
-            format,
-        };
-
-        let swapchain_backend = SwapchainBackendVulkan::load(
+        let mut swapchain_backend = SwapchainBackendVulkan::load(
             *swapchain,
             &session.inner,
             backend.clone(),
             &interop_info,
-        );
-        let interop_handles = swapchain_backend.get_external_memory_handles();
-        let swapchain_frontend = crate::graphics::opengl::frontend::SwapchainFrontendOpenGL::load(
-            &interop_handles,
-            frontend.clone(),
-            &interop_info,
-        );
+            RuntimeTarget {
+                format: runtime_format,
+                extent: vk::Extent2D {
+                    width: runtime_width,
+                    height: runtime_height,
+                },
+            },
+            mode,
+            *tone_mapping,
+            frontend.interop.supports_semaphores(),
+        )
+        .map_err(|result| {
+            unsafe { (session.inner.core.destroy_swapchain)(*swapchain) };
+            result
+        })?;
+        let imported =
+            swapchain_backend
+                .get_external_memory_handles()
+                .and_then(|interop_handles| {
+                    //Without semaphores OpenGL finishes before every release instead
+                    let semaphore_handles = swapchain_backend
+                        .get_external_semaphore_handles()
+                        .unwrap_or_default();
+                    crate::graphics::opengl::frontend::SwapchainFrontendOpenGL::load(
+                        &interop_handles,
+                        &semaphore_handles,
+                        frontend.clone(),
+                        &interop_info,
+                        SwapchainBackendVulkan::interop_layout(&interop_info, mode),
+                    )
+                    .map(|swapchain_frontend| {
+                        (interop_handles, semaphore_handles, swapchain_frontend)
+                    })
+                });
+        let (interop_handles, semaphore_handles, swapchain_frontend) = match imported {
+            Ok(imported) => imported,
+            Err(result) => {
+                swapchain_backend.destroy();
+                unsafe { (session.inner.core.destroy_swapchain)(*swapchain) };
+                return Err(result);
+            }
+        };
+        if !swapchain_frontend.uses_semaphores() {
+            swapchain_backend.drop_semaphores();
+        }
 
         Arc::new(SwapchainWrapper {
             handle: *swapchain,
@@ -138,11 +267,15 @@ fn create_swapchain(
             graphics: SwapchainGraphics::Compat {
                 frontend: Box::new(swapchain_frontend),
                 interop: interop_handles,
+                interop_semaphores: semaphore_handles,
                 backend: Box::new(swapchain_backend),
             },
             acquired_images: Default::default(),
+            graphics_destroyed: AtomicBool::new(false),
             width: create_info.width,
             height: create_info.height,
+            runtime_width,
+            runtime_height,
             layers: create_info.array_size,
         })
     } else {
@@ -156,8 +289,11 @@ fn create_swapchain(
             inner: session.inner.clone(),
             graphics: SwapchainGraphics::Direct,
             acquired_images: Default::default(),
+            graphics_destroyed: AtomicBool::new(false),
             width: create_info.width,
             height: create_info.height,
+            runtime_width: create_info.width,
+            runtime_height: create_info.height,
             layers: create_info.array_size,
         })
     };
@@ -168,3 +304,666 @@ fn create_swapchain(
 
     Ok(xr::Result::SUCCESS)
 }
+
+/// Checks the parameters the compat path cannot (yet) handle so they fail instead of panicking
+fn validate_compat_create_info(
+    create_info: &xr::SwapchainCreateInfo,
+    interop_info: &graphics_interop::ImageCreateInfo,
+    runtime_format: ImageFormat,
+    mode: CopyMode,
+    backend: &VkBackend,
+) -> Result<()> {
+    let format = interop_info.format;
+    let fail = |result: xr::Result, problem: std::fmt::Arguments| {
+        warn!("Failed to create swapchain: {}", problem);
+        Err(result)
+    };
+
+    if create_info.width == 0 || create_info.height == 0 {
+        return fail(
+            xr::Result::ERROR_VALIDATION_FAILURE,
+            format_args!("invalid size {}x{}", create_info.width, create_info.height),
+        );
+    }
+    if create_info.sample_count == 0 || create_info.mip_count == 0 || create_info.array_size == 0 {
+        return fail(
+            xr::Result::ERROR_VALIDATION_FAILURE,
+            format_args!(
+                "sample_count {}, mip_count {} and array_size {} must not be 0",
+                create_info.sample_count, create_info.mip_count, create_info.array_size
+            ),
+        );
+    }
+    if create_info.face_count != 1 && create_info.face_count != 6 {
+        return fail(
+            xr::Result::ERROR_VALIDATION_FAILURE,
+            format_args!("face_count {} is not 1 or 6", create_info.face_count),
+        );
+    }
+
+    let known_flags =
+        xr::SwapchainCreateFlags::PROTECTED_CONTENT | xr::SwapchainCreateFlags::STATIC_IMAGE;
+    if !known_flags.contains(create_info.create_flags) {
+        return fail(
+            xr::Result::ERROR_VALIDATION_FAILURE,
+            format_args!("unknown create_flags {:?}", create_info.create_flags),
+        );
+    }
+    //Protected memory can't be shared between OpenGL and Vulkan
+    if create_info
+        .create_flags
+        .contains(xr::SwapchainCreateFlags::PROTECTED_CONTENT)
+    {
+        return fail(
+            xr::Result::ERROR_FEATURE_UNSUPPORTED,
+            format_args!("protected content swapchains are not supported"),
+        );
+    }
+
+    if create_info.face_count == 6 && create_info.width != create_info.height {
+        return fail(
+            xr::Result::ERROR_VALIDATION_FAILURE,
+            format_args!(
+                "cubemap faces must be square, got {}x{}",
+                create_info.width, create_info.height
+            ),
+        );
+    }
+    let max_mip_count = 32 - create_info.width.max(create_info.height).leading_zeros();
+    if create_info.mip_count > max_mip_count {
+        return fail(
+            xr::Result::ERROR_VALIDATION_FAILURE,
+            format_args!(
+                "mip_count {} is more than the {} levels a {}x{} image has",
+                create_info.mip_count, max_mip_count, create_info.width, create_info.height
+            ),
+        );
+    }
+    if create_info.sample_count > 1 && (create_info.mip_count > 1 || create_info.face_count > 1) {
+        return fail(
+            xr::Result::ERROR_FEATURE_UNSUPPORTED,
+            format_args!("multisampled swapchains can't have mips or faces"),
+        );
+    }
+    //Only the draw tone maps, levels above 0 and faces are blitted
+    if format.needs_tone_mapping(runtime_format)
+        && (create_info.mip_count > 1 || create_info.face_count > 1)
+    {
+        return fail(
+            xr::Result::ERROR_FEATURE_UNSUPPORTED,
+            format_args!(
+                "{:?} swapchains backed by {:?} can't have mips or faces",
+                format, runtime_format
+            ),
+        );
+    }
+    //Depth can't be resolved by averaging so there is nothing sensible to hand the runtime
+    if create_info.sample_count > 1 && format.is_depth() {
+        return fail(
+            xr::Result::ERROR_FEATURE_UNSUPPORTED,
+            format_args!("multisampled depth swapchains are not supported"),
+        );
+    }
+
+    let limits = unsafe {
+        backend
+            .instance
+            .get_physical_device_properties(backend.physical_device)
+    }
+    .limits;
+
+    //Multisampled swapchains are resolved into the runtime's single sample images
+    let sample_count = vk::SampleCountFlags::from_raw(create_info.sample_count);
+    if !create_info.sample_count.is_power_of_two()
+        || !limits
+            .framebuffer_color_sample_counts
+            .contains(sample_count)
+    {
+        return fail(
+            xr::Result::ERROR_FEATURE_UNSUPPORTED,
+            format_args!(
+                "sample_count {} is not supported by the device",
+                create_info.sample_count
+            ),
+        );
+    }
+    //Every cubemap face is a layer of the underlying image
+    let layers = match create_info.array_size.checked_mul(create_info.face_count) {
+        Some(layers) => layers,
+        None => {
+            return fail(
+                xr::Result::ERROR_VALIDATION_FAILURE,
+                format_args!(
+                    "array_size {} with face_count {} overflows",
+                    create_info.array_size, create_info.face_count
+                ),
+            )
+        }
+    };
+    let max_layers = limits
+        .max_image_array_layers
+        .min(limits.max_framebuffer_layers);
+    if layers > max_layers {
+        return fail(
+            xr::Result::ERROR_FEATURE_UNSUPPORTED,
+            format_args!(
+                "array_size {} with face_count {} is more than the supported {} layers",
+                create_info.array_size, create_info.face_count, max_layers
+            ),
+        );
+    }
+
+    //The interop image carries the application's usage, which not every format supports
+    let image_format_properties = unsafe {
+        backend
+            .instance
+            .get_physical_device_image_format_properties(
+                backend.physical_device,
+                format.to_vk().unwrap(),
+                vk::ImageType::TYPE_2D,
+                vk::ImageTiling::OPTIMAL,
+                interop_info.usage.to_vk(),
+                interop_info.vk_create_flags(),
+            )
+    };
+    match image_format_properties {
+        Ok(properties) if properties.sample_counts.contains(sample_count) => (),
+        _ => {
+            return fail(
+                xr::Result::ERROR_FEATURE_UNSUPPORTED,
+                format_args!(
+                    "{:?} swapchains with usage {:?} and sample_count {} are not supported by the device",
+                    format, interop_info.usage, create_info.sample_count
+                ),
+            )
+        }
+    }
+
+    //Depth, levels above 0 and plain color copies are flipped with blits
+    if SwapchainBackendVulkan::uses_blits(interop_info, mode)
+        && !backend.can_blit(format.to_vk().unwrap(), runtime_format.to_vk().unwrap())
+    {
+        return fail(
+            xr::Result::ERROR_FEATURE_UNSUPPORTED,
+            format_args!(
+                "{:?} swapchains with {} levels can't be blitted into {:?} by the device",
+                format, create_info.mip_count, runtime_format
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+unsafe extern "system" fn xr_end_frame(
+    session: xr::Session,
+    frame_end_info: *const xr::FrameEndInfo,
+) -> xr::Result {
+    session.run(|session| end_frame(session, &*frame_end_info))
+}
+
+/// Converts the alpha of compat swapchains the way `CompositionSettings` asks for and removes
+/// the flags describing it from their layers, then copies the released images its layers show
+///
+/// Converting alpha always batches copies, so the conversion set here is what gets copied.
+fn end_frame(session: &SessionWrapper, frame_end_info: &xr::FrameEndInfo) -> Result<xr::Result> {
+    let settings = match (&session.graphics, session.instance.upgrade()) {
+        (SessionGraphics::Compat { .. }, Some(instance)) => instance.settings.composition.clone(),
+        _ => {
+            return unsafe { (session.inner.core.end_frame)(session.handle, frame_end_info) }
+                .result()
+        }
+    };
+
+    //XR_COMPOSITION_LAYER_INVERTED_ALPHA_BIT_EXT is newer than our bindings
+    let inverted_alpha = xr::CompositionLayerFlags::from_raw(0x8);
+    let mut handled = xr::CompositionLayerFlags::EMPTY;
+    if settings.premultiply_alpha {
+        handled |= xr::CompositionLayerFlags::UNPREMULTIPLIED_ALPHA;
+    }
+    if settings.invert_alpha {
+        handled |= inverted_alpha;
+    }
+
+    let layers = if frame_end_info.layer_count > 0 {
+        unsafe {
+            std::slice::from_raw_parts(frame_end_info.layers, frame_end_info.layer_count as usize)
+        }
+    } else {
+        &[]
+    };
+    let found = layers
+        .iter()
+        .map(|&layer| unsafe { layer_swapchains(layer) })
+        .collect::<Vec<_>>();
+
+    //Swapchains the layer can't convert, their layers keep their flags
+    let mut blocked = HashSet::new();
+    if settings.converts_alpha() {
+        let mut conversions = HashMap::new();
+        for (&layer, found) in layers.iter().zip(&found) {
+            let (swapchains, _) = match found {
+                Some(found) => found,
+                None => continue,
+            };
+            let flags = unsafe { (*layer).layer_flags };
+            let mut conversion = AlphaConversion::empty();
+            //Alpha is ignored unless the layer is blended
+            if flags.contains(xr::CompositionLayerFlags::BLEND_TEXTURE_SOURCE_ALPHA) {
+                if flags.contains(inverted_alpha) && settings.invert_alpha {
+                    conversion |= AlphaConversion::INVERT;
+                }
+                if flags.contains(xr::CompositionLayerFlags::UNPREMULTIPLIED_ALPHA)
+                    && settings.premultiply_alpha
+                {
+                    conversion |= AlphaConversion::PREMULTIPLY;
+                }
+            }
+            for &swapchain in swapchains {
+                if *conversions.entry(swapchain).or_insert(conversion) != conversion
+                    && blocked.insert(swapchain)
+                {
+                    warn!(
+                        "{:?} is shown with different alpha flags, leaving them to the runtime",
+                        swapchain
+                    );
+                }
+            }
+        }
+
+        for (swapchain, conversion) in conversions {
+            if blocked.contains(&swapchain) {
+                continue;
+            }
+            let converted = match session.swapchains.get(&swapchain) {
+                Some(swapchain) => match &swapchain.graphics {
+                    SwapchainGraphics::Compat { backend, .. } => {
+                        backend.set_alpha_conversion(conversion)
+                    }
+                    SwapchainGraphics::Direct => conversion.is_empty(),
+                },
+                None => false,
+            };
+            if !converted {
+                //Logged every frame, so not as a warning
+                debug!(
+                    "Can't convert the alpha of {:?}, leaving its layers' flags as they are",
+                    swapchain
+                );
+                blocked.insert(swapchain);
+            }
+        }
+
+        //A layer's swapchains are either all converted or none are
+        loop {
+            let mut changed = false;
+            for (swapchains, _) in found.iter().flatten() {
+                if swapchains
+                    .iter()
+                    .any(|swapchain| blocked.contains(swapchain))
+                {
+                    for &swapchain in swapchains {
+                        changed |= blocked.insert(swapchain);
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        for swapchain in &blocked {
+            if let Some(swapchain) = session.swapchains.get(swapchain) {
+                if let SwapchainGraphics::Compat { backend, .. } = &swapchain.graphics {
+                    backend.set_alpha_conversion(AlphaConversion::empty());
+                }
+            }
+        }
+    }
+
+    //Rewritten layers are copied in here, they have to outlive the call into the runtime
+    let mut storage = Vec::<Box<dyn Any>>::new();
+    let layers = layers
+        .iter()
+        .zip(&found)
+        .map(|(&layer, found)| unsafe {
+            let (swapchains, copy) = match found {
+                Some(found) => found,
+                None => return layer,
+            };
+            let flags = (*layer).layer_flags;
+            let converted = flags.intersects(handled)
+                && !swapchains
+                    .iter()
+                    .any(|swapchain| blocked.contains(swapchain));
+            if converted
+                || swapchains
+                    .iter()
+                    .any(|&swapchain| is_upscaled(session, swapchain))
+            {
+                let flags = if converted {
+                    xr::CompositionLayerFlags::from_raw(flags.into_raw() & !handled.into_raw())
+                } else {
+                    flags
+                };
+                copy(layer, flags, session, &mut storage)
+            } else {
+                layer
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if session.batches_copies() {
+        //Swapchains no layer shows stay held, unless a layer's swapchains are unknown
+        let referenced = layers
+            .iter()
+            .zip(&found)
+            .map(|(&layer, found)| {
+                found.as_ref().map(|(swapchains, _)| {
+                    let mut swapchains = swapchains.clone();
+                    swapchains.extend(unsafe { depth_swapchains(layer) });
+                    swapchains
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|swapchains| swapchains.concat());
+        flush_releases(session, None, referenced.as_deref())?;
+    }
+
+    let frame_end_info = xr::FrameEndInfo {
+        layers: if layers.is_empty() {
+            frame_end_info.layers
+        } else {
+            layers.as_ptr()
+        },
+        ..*frame_end_info
+    };
+    unsafe { (session.inner.core.end_frame)(session.handle, &frame_end_info) }.result()
+}
+
+/// Copies the images released since the last flush in one submission and hands them back to the
+/// runtime, if `only` is given the other swapchains' images stay held and so do the images of
+/// swapchains missing from `referenced`
+///
+/// A later frame may show the last released image without acquiring again, so held images are
+/// never released uncopied. If the submission fails the images are still handed back.
+pub(super) fn flush_releases(
+    session: &SessionWrapper,
+    only: Option<xr::Swapchain>,
+    referenced: Option<&[xr::Swapchain]>,
+) -> Result<()> {
+    let backend = match &session.graphics {
+        SessionGraphics::Compat { backend, .. } => backend,
+        _ => return Ok(()),
+    };
+    let pending = {
+        let mut lock = session.pending_releases.lock().unwrap();
+        let (pending, held): (Vec<_>, Vec<_>) = lock.drain(..).partition(|(handle, _)| {
+            only.map_or(true, |only| *handle == only)
+                && referenced.map_or(true, |referenced| referenced.contains(handle))
+        });
+        *lock = held;
+        pending
+    };
+    //Destroyed swapchains have nothing left to release
+    let pending = pending
+        .into_iter()
+        .filter_map(|(handle, index)| {
+            session
+                .swapchains
+                .get(&handle)
+                .map(|swapchain| (swapchain.clone(), index))
+        })
+        .collect::<Vec<_>>();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    for (swapchain, index) in &pending {
+        if let SwapchainGraphics::Compat { backend, .. } = &swapchain.graphics {
+            backend.queue_release(*index as usize);
+        }
+    }
+    //The fences are signalled even if this fails, so nothing waits on them forever
+    let submitted = backend.submit_queued_copies().map_err(|result| {
+        error!("Failed to submit the batched copies: {}", result);
+        xr::Result::ERROR_RUNTIME_FAILURE
+    });
+
+    let release_info = xr::SwapchainImageReleaseInfo {
+        ty: xr::SwapchainImageReleaseInfo::TYPE,
+        next: std::ptr::null(),
+    };
+    for (swapchain, index) in &pending {
+        //The application's release already succeeded, so all that can be done is report it
+        if let Err(result) = unsafe {
+            (swapchain.inner.core.release_swapchain_image)(swapchain.handle, &release_info)
+        }
+        .result()
+        {
+            warn!(
+                "Runtime failed to release image {} of {:?}: {}",
+                index, swapchain.handle, result
+            );
+        }
+    }
+    submitted
+}
+
+type CopyLayer = unsafe fn(
+    *const xr::CompositionLayerBaseHeader,
+    xr::CompositionLayerFlags,
+    &SessionWrapper,
+    &mut Vec<Box<dyn Any>>,
+) -> *const xr::CompositionLayerBaseHeader;
+
+/// The swapchains a layer reads and how to copy it, `None` for layers the layer doesn't know
+unsafe fn layer_swapchains(
+    layer: *const xr::CompositionLayerBaseHeader,
+) -> Option<(Vec<xr::Swapchain>, CopyLayer)> {
+    Some(match (*layer).ty {
+        xr::StructureType::COMPOSITION_LAYER_PROJECTION => {
+            let projection = &*(layer as *const xr::CompositionLayerProjection);
+            let views =
+                std::slice::from_raw_parts(projection.views, projection.view_count as usize);
+            (
+                views.iter().map(|view| view.sub_image.swapchain).collect(),
+                copy_layer::<xr::CompositionLayerProjection>,
+            )
+        }
+        xr::StructureType::COMPOSITION_LAYER_QUAD => (
+            vec![
+                (*(layer as *const xr::CompositionLayerQuad))
+                    .sub_image
+                    .swapchain,
+            ],
+            copy_layer::<xr::CompositionLayerQuad>,
+        ),
+        xr::StructureType::COMPOSITION_LAYER_CYLINDER_KHR => (
+            vec![
+                (*(layer as *const xr::CompositionLayerCylinderKHR))
+                    .sub_image
+                    .swapchain,
+            ],
+            copy_layer::<xr::CompositionLayerCylinderKHR>,
+        ),
+        xr::StructureType::COMPOSITION_LAYER_EQUIRECT_KHR => (
+            vec![
+                (*(layer as *const xr::CompositionLayerEquirectKHR))
+                    .sub_image
+                    .swapchain,
+            ],
+            copy_layer::<xr::CompositionLayerEquirectKHR>,
+        ),
+        xr::StructureType::COMPOSITION_LAYER_EQUIRECT2_KHR => (
+            vec![
+                (*(layer as *const xr::CompositionLayerEquirect2KHR))
+                    .sub_image
+                    .swapchain,
+            ],
+            copy_layer::<xr::CompositionLayerEquirect2KHR>,
+        ),
+        xr::StructureType::COMPOSITION_LAYER_CUBE_KHR => (
+            vec![(*(layer as *const xr::CompositionLayerCubeKHR)).swapchain],
+            copy_layer::<xr::CompositionLayerCubeKHR>,
+        ),
+        _ => return None,
+    })
+}
+
+/// The depth swapchains a projection layer's views chain
+unsafe fn depth_swapchains(layer: *const xr::CompositionLayerBaseHeader) -> Vec<xr::Swapchain> {
+    if (*layer).ty != xr::StructureType::COMPOSITION_LAYER_PROJECTION {
+        return Vec::new();
+    }
+    let projection = &*(layer as *const xr::CompositionLayerProjection);
+    let views = std::slice::from_raw_parts(projection.views, projection.view_count as usize);
+    let mut swapchains = Vec::new();
+    for view in views {
+        let mut next = view.next as *const xr::BaseInStructure;
+        while !next.is_null() {
+            if (*next).ty == xr::StructureType::COMPOSITION_LAYER_DEPTH_INFO_KHR {
+                let depth = &*(next as *const xr::CompositionLayerDepthInfoKHR);
+                swapchains.push(depth.sub_image.swapchain);
+            }
+            next = (*next).next;
+        }
+    }
+    swapchains
+}
+
+/// Copies a layer of type `T` into `storage` with its flags replaced and its sub images scaled
+/// to the runtime's swapchains
+unsafe fn copy_layer<T: CopyableLayer>(
+    layer: *const xr::CompositionLayerBaseHeader,
+    flags: xr::CompositionLayerFlags,
+    session: &SessionWrapper,
+    storage: &mut Vec<Box<dyn Any>>,
+) -> *const xr::CompositionLayerBaseHeader {
+    let mut copy = Box::new(*(layer as *const T));
+    //Every layer starts with the base header
+    (*(copy.as_mut() as *mut T as *mut xr::CompositionLayerBaseHeader)).layer_flags = flags;
+    copy.scale(session, storage);
+    let copy_ptr = copy.as_ref() as *const T as *const xr::CompositionLayerBaseHeader;
+    storage.push(copy);
+    copy_ptr
+}
+
+/// A layer `copy_layer` knows how to rewrite
+trait CopyableLayer: Copy + 'static {
+    /// Scales the rects of the layer's sub images, anything they point to is copied into
+    /// `storage`
+    unsafe fn scale(&mut self, session: &SessionWrapper, storage: &mut Vec<Box<dyn Any>>);
+}
+
+impl CopyableLayer for xr::CompositionLayerProjection {
+    unsafe fn scale(&mut self, session: &SessionWrapper, storage: &mut Vec<Box<dyn Any>>) {
+        let mut views = std::slice::from_raw_parts(self.views, self.view_count as usize).to_vec();
+        for view in &mut views {
+            scale_sub_image(session, &mut view.sub_image);
+            //Only depth info at the start of the chain is found
+            let depth = view.next as *const xr::CompositionLayerDepthInfoKHR;
+            if !depth.is_null() && (*depth).ty == xr::CompositionLayerDepthInfoKHR::TYPE {
+                let mut depth = Box::new(*depth);
+                scale_sub_image(session, &mut depth.sub_image);
+                view.next = depth.as_ref() as *const _ as *const _;
+                storage.push(depth);
+            }
+        }
+        self.views = views.as_ptr();
+        storage.push(Box::new(views));
+    }
+}
+
+impl CopyableLayer for xr::CompositionLayerQuad {
+    unsafe fn scale(&mut self, session: &SessionWrapper, _: &mut Vec<Box<dyn Any>>) {
+        scale_sub_image(session, &mut self.sub_image);
+    }
+}
+
+impl CopyableLayer for xr::CompositionLayerCylinderKHR {
+    unsafe fn scale(&mut self, session: &SessionWrapper, _: &mut Vec<Box<dyn Any>>) {
+        scale_sub_image(session, &mut self.sub_image);
+    }
+}
+
+impl CopyableLayer for xr::CompositionLayerEquirectKHR {
+    unsafe fn scale(&mut self, session: &SessionWrapper, _: &mut Vec<Box<dyn Any>>) {
+        scale_sub_image(session, &mut self.sub_image);
+    }
+}
+
+impl CopyableLayer for xr::CompositionLayerEquirect2KHR {
+    unsafe fn scale(&mut self, session: &SessionWrapper, _: &mut Vec<Box<dyn Any>>) {
+        scale_sub_image(session, &mut self.sub_image);
+    }
+}
+
+//Cubemaps are never scaled and have no rect
+impl CopyableLayer for xr::CompositionLayerCubeKHR {
+    unsafe fn scale(&mut self, _: &SessionWrapper, _: &mut Vec<Box<dyn Any>>) {}
+}
+
+fn is_upscaled(session: &SessionWrapper, swapchain: xr::Swapchain) -> bool {
+    session.swapchains.get(&swapchain).is_some_and(|swapchain| {
+        swapchain.runtime_width != swapchain.width || swapchain.runtime_height != swapchain.height
+    })
+}
+
+/// Moves a rect of the application's swapchain to where it ends up in the runtime's
+fn scale_sub_image(session: &SessionWrapper, sub_image: &mut xr::SwapchainSubImage) {
+    let swapchain = match session.swapchains.get(&sub_image.swapchain) {
+        Some(swapchain) => swapchain,
+        None => return,
+    };
+    let scale = |value: i32, runtime: u32, app: u32| {
+        (value as i64 * runtime as i64 / app.max(1) as i64) as i32
+    };
+    let rect = &mut sub_image.image_rect;
+    rect.offset.x = scale(rect.offset.x, swapchain.runtime_width, swapchain.width);
+    rect.offset.y = scale(rect.offset.y, swapchain.runtime_height, swapchain.height);
+    rect.extent.width = scale(rect.extent.width, swapchain.runtime_width, swapchain.width);
+    rect.extent.height = scale(
+        rect.extent.height,
+        swapchain.runtime_height,
+        swapchain.height,
+    );
+}
+
+fn image_usage(usage_flags: xr::SwapchainUsageFlags) -> ImageUsage {
+    [
+        (
+            xr::SwapchainUsageFlags::COLOR_ATTACHMENT,
+            ImageUsage::COLOR_ATTACHMENT,
+        ),
+        (
+            xr::SwapchainUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ImageUsage::DEPTH_STENCIL_ATTACHMENT,
+        ),
+        (
+            xr::SwapchainUsageFlags::UNORDERED_ACCESS,
+            ImageUsage::STORAGE,
+        ),
+        (
+            xr::SwapchainUsageFlags::TRANSFER_SRC,
+            ImageUsage::TRANSFER_SRC,
+        ),
+        (
+            xr::SwapchainUsageFlags::TRANSFER_DST,
+            ImageUsage::TRANSFER_DST,
+        ),
+        (xr::SwapchainUsageFlags::SAMPLED, ImageUsage::SAMPLED),
+        (
+            xr::SwapchainUsageFlags::MUTABLE_FORMAT,
+            ImageUsage::MUTABLE_FORMAT,
+        ),
+        (
+            xr::SwapchainUsageFlags::INPUT_ATTACHMENT_MND,
+            ImageUsage::INPUT_ATTACHMENT,
+        ),
+    ]
+    .into_iter()
+    .filter(|(xr_usage, _)| usage_flags.contains(*xr_usage))
+    .fold(ImageUsage::empty(), |usage, (_, image_usage)| {
+        usage | image_usage
+    })
+}
@@ -1,3 +1,4 @@
+use log::info;
 use openxr::{
     sys::{self as xr, pfn},
     Result,
@@ -19,7 +20,9 @@ pub(super) unsafe fn get_swapchain_interceptors(name: &str) -> Option<pfn::VoidF
             transmute(xr_enumerate_swapchain_images as EnumerateSwapchainImages)
         }
         "xrAcquireSwapchainImage" => transmute(xr_acquire_swapchain_image as AcquireSwapchainImage),
+        "xrWaitSwapchainImage" => transmute(xr_wait_swapchain_image as WaitSwapchainImage),
         "xrReleaseSwapchainImage" => transmute(xr_release_swapchain_image as ReleaseSwapchainImage),
+        "xrDestroySwapchain" => transmute(xr_destroy_swapchain as DestroySwapchain),
         _ => return None,
     })
 }
@@ -53,6 +56,13 @@ unsafe extern "system" fn xr_acquire_swapchain_image(
     swapchain.run(|swapchain| acquire_swapchain_image(swapchain, &*acquire_info, &mut *index))
 }
 
+unsafe extern "system" fn xr_wait_swapchain_image(
+    swapchain: xr::Swapchain,
+    wait_info: *const xr::SwapchainImageWaitInfo,
+) -> xr::Result {
+    swapchain.run(|swapchain| wait_swapchain_image(swapchain, &*wait_info))
+}
+
 unsafe extern "system" fn xr_release_swapchain_image(
     swapchain: xr::Swapchain,
     release_info: *const xr::SwapchainImageReleaseInfo,
@@ -60,11 +70,33 @@ unsafe extern "system" fn xr_release_swapchain_image(
     swapchain.run(|swapchain| release_swapchain_image(swapchain, &*release_info))
 }
 
+unsafe extern "system" fn xr_destroy_swapchain(swapchain: xr::Swapchain) -> xr::Result {
+    swapchain.run_destroy(|swapchain| destroy_swapchain(swapchain))
+}
+
+pub(super) fn destroy_swapchain(swapchain: &SwapchainWrapper) -> Result<xr::Result> {
+    //A failed copy was already logged and the swapchain goes either way
+    let _ = flush_pending_release(swapchain);
+    swapchain.destroy_graphics();
+
+    let result = unsafe { (swapchain.inner.core.destroy_swapchain)(swapchain.handle) }.result();
+
+    xr::Swapchain::all_wrappers().remove(&swapchain.handle);
+    if let Some(session) = swapchain.session.upgrade() {
+        session.swapchains.remove(&swapchain.handle);
+    }
+    info!("Swapchain destroyed: {:?}", swapchain.handle);
+
+    result
+}
+
 fn acquire_swapchain_image(
     swapchain: &SwapchainWrapper,
     acquire_info: &xr::SwapchainImageAcquireInfo,
     index: &mut u32,
 ) -> Result<xr::Result> {
+    flush_pending_release(swapchain)?;
+
     let success = unsafe {
         (swapchain.inner.core.acquire_swapchain_image)(swapchain.handle, acquire_info, index)
     }
@@ -75,7 +107,69 @@ fn acquire_swapchain_image(
         .lock()
         .unwrap()
         .get_mut()
-        .push_back(*index);
+        .push_back((*index, false));
+
+    Ok(success)
+}
+
+fn wait_swapchain_image(
+    swapchain: &SwapchainWrapper,
+    wait_info: &xr::SwapchainImageWaitInfo,
+) -> Result<xr::Result> {
+    let backend = match &swapchain.graphics {
+        SwapchainGraphics::Compat { backend, .. } => backend,
+        SwapchainGraphics::Direct => {
+            return unsafe {
+                (swapchain.inner.core.wait_swapchain_image)(swapchain.handle, wait_info)
+            }
+            .result()
+        }
+    };
+
+    //The oldest acquired image that hasn't been waited on yet
+    let index = swapchain
+        .acquired_images
+        .lock()
+        .unwrap()
+        .get_mut()
+        .iter()
+        .find(|(_, waited)| !waited)
+        .map(|&(index, _)| index);
+
+    let start = std::time::Instant::now();
+    let timeout = wait_info.timeout.as_nanos();
+
+    //The previous copy out of this image may still be running
+    if let Some(index) = index {
+        if !backend.wait_image(index as usize, timeout.max(0) as u64) {
+            return Ok(xr::Result::TIMEOUT_EXPIRED);
+        }
+    }
+
+    //The runtime gets whatever is left of the timeout
+    let remaining = if timeout == xr::Duration::INFINITE.as_nanos() {
+        timeout
+    } else {
+        (timeout - start.elapsed().as_nanos() as i64).max(0)
+    };
+    let wait_info = xr::SwapchainImageWaitInfo {
+        timeout: xr::Duration::from_nanos(remaining),
+        ..*wait_info
+    };
+    let success =
+        unsafe { (swapchain.inner.core.wait_swapchain_image)(swapchain.handle, &wait_info) }
+            .result()?;
+
+    if success == xr::Result::SUCCESS {
+        let mut lock = swapchain.acquired_images.lock().unwrap();
+        if let Some(entry) = lock
+            .get_mut()
+            .iter_mut()
+            .find(|(image, waited)| Some(*image) == index && !waited)
+        {
+            entry.1 = true;
+        }
+    }
 
     Ok(success)
 }
@@ -91,50 +185,24 @@ fn release_swapchain_image(
         frontend, backend, ..
     } = &swapchain.graphics
     {
-        let index = *queue.front().unwrap();
+        let (index, _) = *queue.front().unwrap();
         //TODO better sub resource memory format transitions
         frontend.release_image(index);
-        backend.release_image(index as usize);
-        // let runtime_image = backend.runtime_images[index as usize];
-        // let image = backend.images[index as usize];
-        // vk_base.record_submit_commandbuffer(
-        //     vk_base.command_buffer,
-        //     vk::Fence::null(),
-        //     &[],
-        //     &[],
-        //     &[],
-        //     |device, cmd| {
-        //         device.cmd_copy_image(
-        //             cmd,
-        //             image,
-        //             vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-        //             runtime_image,
-        //             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-        //             &[vk::ImageCopy {
-        //                 src_subresource: vk::ImageSubresourceLayers {
-        //                     aspect_mask: vk::ImageAspectFlags::COLOR,
-        //                     mip_level: 0,
-        //                     base_array_layer: 0,
-        //                     layer_count: 1,
-        //                 },
-        //                 src_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
-        //                 dst_subresource: vk::ImageSubresourceLayers {
-        //                     aspect_mask: vk::ImageAspectFlags::COLOR,
-        //                     mip_level: 0,
-        //                     base_array_layer: 0,
-        //                     layer_count: 1,
-        //                 },
-        //                 dst_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
-        //                 extent: vk::Extent3D {
-        //                     width: swapchain.width,
-        //                     height: swapchain.height,
-        //                     depth: 1,
-        //                 },
-        //             }],
-        //         )
-        //     },
-        // );
-        // vk_base.device.device_wait_idle().unwrap();
+
+        match swapchain.session.upgrade() {
+            //The copy and the runtime's release happen in xrEndFrame
+            Some(session) if session.batches_copies() => {
+                backend.hold_release(index as usize)?;
+                session
+                    .pending_releases
+                    .lock()
+                    .unwrap()
+                    .push((swapchain.handle, index));
+                queue.pop_front().unwrap();
+                return Ok(xr::Result::SUCCESS);
+            }
+            _ => backend.release_image(index as usize)?,
+        }
     }
     let success =
         unsafe { (swapchain.inner.core.release_swapchain_image)(swapchain.handle, release_info) }
@@ -144,3 +212,14 @@ fn release_swapchain_image(
 
     Ok(success)
 }
+
+/// Copies and releases the batched image of `swapchain` now, the runtime needs it back
+/// before handing out another image or destroying the swapchain
+fn flush_pending_release(swapchain: &SwapchainWrapper) -> Result<()> {
+    match swapchain.session.upgrade() {
+        Some(session) if session.has_pending_release(swapchain.handle) => {
+            super::session::flush_releases(&session, Some(swapchain.handle), None)
+        }
+        _ => Ok(()),
+    }
+}
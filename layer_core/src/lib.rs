@@ -1,8 +1,12 @@
+mod active_runtime;
 mod entry;
 mod graphics;
 pub mod interceptors;
 #[allow(dead_code)]
 pub mod loader_interfaces;
+pub mod logging;
+pub mod quirks;
+pub mod settings;
 pub mod wrappers;
 
 use log::trace;
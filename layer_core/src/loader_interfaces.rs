@@ -3,6 +3,13 @@ use openxr::sys::*;
 pub const XR_CURRENT_LOADER_API_LAYER_VERSION: u32 = 1;
 pub const XR_CURRENT_LOADER_RUNTIME_VERSION: u32 = 1;
 
+pub const XR_LOADER_INFO_STRUCT_VERSION: u32 = 1;
+pub const XR_RUNTIME_INFO_STRUCT_VERSION: u32 = 1;
+
+//XrLoaderInterfaceStructs, stored in the `ty` fields below
+pub const XR_LOADER_INTERFACE_STRUCT_LOADER_INFO: i32 = 1;
+pub const XR_LOADER_INTERFACE_STRUCT_RUNTIME_REQUEST: i32 = 3;
+
 pub type FnCreateApiLayerInstance = unsafe extern "system" fn(
     info: *const InstanceCreateInfo,
     api_layer_info: *const ApiLayerCreateInfo,
@@ -0,0 +1,479 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, Once, OnceLock, RwLock},
+    time::Instant,
+};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+pub const LOG_ENV: &str = "SORENON_LAYER_LOG";
+pub const LOG_OUTPUT_ENV: &str = "SORENON_LAYER_LOG_OUTPUT";
+pub const LOG_FILE_ENV: &str = "SORENON_LAYER_LOG_FILE";
+pub const LOG_MAX_SIZE_ENV: &str = "SORENON_LAYER_LOG_MAX_SIZE";
+pub const LOG_MAX_FILES_ENV: &str = "SORENON_LAYER_LOG_MAX_FILES";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogOutput {
+    Stderr,
+    File,
+    Syslog,
+}
+
+impl FromStr for LogOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "stderr" | "terminal" => Ok(LogOutput::Stderr),
+            "file" => Ok(LogOutput::File),
+            "syslog" | "journald" => Ok(LogOutput::Syslog),
+            other => Err(format!("unknown log output `{}`", other)),
+        }
+    }
+}
+
+/// A global level plus per-module overrides, in the same `warn,layer_core::graphics=trace`
+/// syntax as `env_logger`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    pub default: LevelFilter,
+    pub modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = LogFilter {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        };
+
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let level = level
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("invalid log level `{}`", level))?;
+                    filter.modules.push((module.trim().to_owned(), level));
+                }
+                None => {
+                    filter.default = directive
+                        .parse()
+                        .map_err(|_| format!("invalid log level `{}`", directive))?;
+                }
+            }
+        }
+
+        //Longest module paths first so the most specific directive wins
+        filter
+            .modules
+            .sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+
+        Ok(filter)
+    }
+}
+
+impl LogFilter {
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target == module
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            })
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub filter: LogFilter,
+    pub outputs: Vec<LogOutput>,
+    pub file: Option<PathBuf>,
+    /// Size in bytes after which the log file is rotated, 0 disables rotation
+    pub max_size: u64,
+    /// Number of rotated log files to keep around
+    pub max_files: u32,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: LogFilter::default(),
+            outputs: vec![LogOutput::Stderr],
+            file: None,
+            max_size: 8 * 1024 * 1024,
+            max_files: 3,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Applies any `SORENON_LAYER_LOG*` environment variables on top of this config
+    ///
+    /// Returns a list of problems found while parsing so they can be logged once a logger exists
+    pub fn apply_env(&mut self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Ok(filter) = std::env::var(LOG_ENV) {
+            match filter.parse() {
+                Ok(filter) => self.filter = filter,
+                Err(err) => problems.push(format!("{}: {}", LOG_ENV, err)),
+            }
+        }
+
+        if let Ok(outputs) = std::env::var(LOG_OUTPUT_ENV) {
+            match parse_outputs(&outputs) {
+                Ok(outputs) => self.outputs = outputs,
+                Err(err) => problems.push(format!("{}: {}", LOG_OUTPUT_ENV, err)),
+            }
+        }
+
+        if let Some(file) = std::env::var_os(LOG_FILE_ENV) {
+            self.file = Some(PathBuf::from(file));
+        }
+
+        if let Ok(max_size) = std::env::var(LOG_MAX_SIZE_ENV) {
+            match parse_size(&max_size) {
+                Some(max_size) => self.max_size = max_size,
+                None => problems.push(format!("{}: invalid size `{}`", LOG_MAX_SIZE_ENV, max_size)),
+            }
+        }
+
+        if let Ok(max_files) = std::env::var(LOG_MAX_FILES_ENV) {
+            match max_files.trim().parse() {
+                Ok(max_files) => self.max_files = max_files,
+                Err(_) => problems.push(format!(
+                    "{}: invalid file count `{}`",
+                    LOG_MAX_FILES_ENV, max_files
+                )),
+            }
+        }
+
+        problems
+    }
+
+    pub fn file_path(&self) -> Option<PathBuf> {
+        self.file.clone().or_else(|| {
+            dirs::state_dir()
+                .or_else(dirs::data_local_dir)
+                .map(|dir| dir.join("sorenon_openxr_layer").join("layer.log"))
+        })
+    }
+}
+
+/// Parses a comma separated output list, `none` (or an empty list) disables logging entirely
+pub fn parse_outputs(s: &str) -> Result<Vec<LogOutput>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|output| !output.is_empty() && !output.eq_ignore_ascii_case("none"))
+        .map(str::parse)
+        .collect()
+}
+
+/// Parses a byte count with an optional `K`, `M` or `G` suffix
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (digits, multiplier) = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => (&s[..s.len() - 1], 1024),
+        'M' => (&s[..s.len() - 1], 1024 * 1024),
+        'G' => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: u32) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_owned();
+        name.push(format!(".{}", index));
+        self.path.with_file_name(name)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for i in (1..self.max_files).rev() {
+                let from = self.rotated_path(i);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(i + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.max_size != 0 && self.size + line.len() as u64 > self.max_size {
+            if let Err(err) = self.rotate() {
+                eprintln!(
+                    "[sorenon_openxr_layer] Failed to rotate log file `{}`: {}",
+                    self.path.display(),
+                    err
+                );
+                //Keep appending to the old file rather than losing messages
+                self.max_size = 0;
+            }
+        }
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+type SyslogLogger = syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>;
+
+enum Sink {
+    Stderr,
+    File(Mutex<RotatingFile>),
+    #[cfg(target_os = "linux")]
+    Syslog(Mutex<SyslogLogger>),
+}
+
+struct LoggerState {
+    filter: LogFilter,
+    sinks: Vec<Sink>,
+}
+
+struct LayerLogger {
+    start: Instant,
+    state: RwLock<LoggerState>,
+}
+
+impl Log for LayerLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.state.read() {
+            Ok(state) => metadata.level() <= state.filter.level_for(metadata.target()),
+            Err(_) => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let state = match self.state.read() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        if state.sinks.is_empty() || record.level() > state.filter.level_for(record.target()) {
+            return;
+        }
+
+        let elapsed = self.start.elapsed();
+        let line = format!(
+            "[{:>5}.{:03} {:<5} {}] {}\n",
+            elapsed.as_secs(),
+            elapsed.subsec_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        for sink in &state.sinks {
+            match sink {
+                Sink::Stderr => {
+                    let _ = std::io::stderr().write_all(line.as_bytes());
+                }
+                Sink::File(file) => {
+                    if let Ok(mut file) = file.lock() {
+                        file.write_line(&line);
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                Sink::Syslog(syslog) => {
+                    if let Ok(mut syslog) = syslog.lock() {
+                        let message = format!("{}: {}", record.target(), record.args());
+                        let _ = match record.level() {
+                            log::Level::Error => syslog.err(message),
+                            log::Level::Warn => syslog.warning(message),
+                            log::Level::Info => syslog.info(message),
+                            log::Level::Debug | log::Level::Trace => syslog.debug(message),
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(state) = self.state.read() {
+            for sink in &state.sinks {
+                if let Sink::File(file) = sink {
+                    if let Ok(mut file) = file.lock() {
+                        let _ = file.file.flush();
+                    }
+                }
+            }
+        }
+    }
+}
+
+static INIT: Once = Once::new();
+static LOGGER: OnceLock<&'static LayerLogger> = OnceLock::new();
+
+/// Installs the layer's logger configured from the environment
+///
+/// Never fails: any output that cannot be opened is reported on stderr and skipped
+pub fn initialize() {
+    INIT.call_once(|| {
+        let mut config = LogConfig::default();
+        let problems = config.apply_env();
+
+        let logger: &'static LayerLogger = Box::leak(Box::new(LayerLogger {
+            start: Instant::now(),
+            state: RwLock::new(LoggerState {
+                filter: LogFilter::default(),
+                sinks: Vec::new(),
+            }),
+        }));
+
+        if log::set_logger(logger).is_err() {
+            eprintln!(
+                "[sorenon_openxr_layer] A logger was already installed, layer logging is disabled"
+            );
+            return;
+        }
+        let _ = LOGGER.set(logger);
+
+        reconfigure(&config);
+
+        for problem in problems {
+            log::warn!("Ignoring invalid log setting: {}", problem);
+        }
+    });
+}
+
+/// Replaces the active log filter and outputs
+pub fn reconfigure(config: &LogConfig) {
+    let logger = match LOGGER.get() {
+        Some(logger) => logger,
+        None => return,
+    };
+
+    let mut failures = Vec::new();
+    let sinks = config
+        .outputs
+        .iter()
+        .filter_map(|output| match output {
+            LogOutput::Stderr => Some(Sink::Stderr),
+            LogOutput::File => {
+                let path = match config.file_path() {
+                    Some(path) => path,
+                    None => {
+                        failures.push("Could not determine a log file location".to_owned());
+                        return None;
+                    }
+                };
+                match RotatingFile::open(&path, config.max_size, config.max_files) {
+                    Ok(file) => Some(Sink::File(Mutex::new(file))),
+                    Err(err) => {
+                        failures.push(format!(
+                            "Could not open log file `{}`: {}",
+                            path.display(),
+                            err
+                        ));
+                        None
+                    }
+                }
+            }
+            #[cfg(target_os = "linux")]
+            LogOutput::Syslog => {
+                let formatter = syslog::Formatter3164 {
+                    facility: syslog::Facility::LOG_USER,
+                    hostname: None,
+                    process: "sorenon_openxr_layer".to_owned(),
+                    pid: std::process::id(),
+                };
+                match syslog::unix(formatter) {
+                    Ok(syslog) => Some(Sink::Syslog(Mutex::new(syslog))),
+                    Err(err) => {
+                        failures.push(format!("Could not connect to syslog: {}", err));
+                        None
+                    }
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            LogOutput::Syslog => {
+                failures.push("Syslog output is not supported on this platform".to_owned());
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    //Never lose errors entirely, if every requested output failed fall back to stderr
+    let sinks = if sinks.is_empty() && !failures.is_empty() {
+        vec![Sink::Stderr]
+    } else {
+        sinks
+    };
+
+    match logger.state.write() {
+        Ok(mut state) => {
+            state.filter = config.filter.clone();
+            state.sinks = sinks;
+        }
+        Err(_) => return,
+    }
+    log::set_max_level(config.filter.max_level());
+
+    for failure in failures {
+        log::warn!("{}", failure);
+    }
+}
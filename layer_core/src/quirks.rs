@@ -0,0 +1,191 @@
+use std::{fmt::Display, str::FromStr};
+
+use graphics_interop::ImageFormat;
+use log::warn;
+use serde::Deserialize;
+
+use crate::wrappers::instance::Runtime;
+
+/// Extensions the layer knows how to provide itself, with the version it implements
+pub const EMULATABLE_EXTENSIONS: [(&str, u32); 1] = [("XR_KHR_opengl_enable", 10)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RuntimeVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u32,
+}
+
+impl FromStr for RuntimeVersion {
+    type Err = String;
+
+    /// Parses `major[.minor[.patch]]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('.');
+        let mut next = |required| match parts.next() {
+            Some(part) => part
+                .parse::<u32>()
+                .map_err(|_| format!("invalid runtime version `{}`", s)),
+            None if required => Err(format!("invalid runtime version `{}`", s)),
+            None => Ok(0),
+        };
+        let major = next(true)?;
+        let minor = next(false)?;
+        let patch = next(false)?;
+
+        Ok(Self {
+            major: major
+                .try_into()
+                .map_err(|_| format!("invalid runtime version `{}`", s))?,
+            minor: minor
+                .try_into()
+                .map_err(|_| format!("invalid runtime version `{}`", s))?,
+            patch,
+        })
+    }
+}
+
+impl TryFrom<String> for RuntimeVersion {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for RuntimeVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// How the layer works around the quirks of the runtime bellow it
+#[derive(Debug, Clone)]
+pub struct RuntimeQuirks {
+    /// Whether OpenGL sessions are translated to Vulkan when `opengl.mode = "auto"`
+    pub opengl_compat: bool,
+    /// Runtime formats that are never offered to the application
+    pub blocked_formats: Vec<ImageFormat>,
+    /// Extensions the layer exposes when the runtime does not support them itself
+    pub emulated_extensions: Vec<String>,
+    /// Runtime extensions kept from the application, none by default as the layer shares the
+    /// Vulkan extensions it uses with applications that enable them too
+    pub hidden_extensions: Vec<String>,
+}
+
+impl Default for RuntimeQuirks {
+    /// The quirks of a runtime the layer knows nothing about
+    fn default() -> Self {
+        Self {
+            opengl_compat: false,
+            blocked_formats: Vec::new(),
+            emulated_extensions: EMULATABLE_EXTENSIONS
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            hidden_extensions: Vec::new(),
+        }
+    }
+}
+
+/// A `[[runtime]]` entry in the settings file, overriding the built-in quirk table
+///
+/// `runtime` is either one of `steamvr`, `oculus`, `wmr` and `monado`, the exact runtime name
+/// or `*` for every runtime
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RuntimeOverride {
+    pub runtime: String,
+    pub min_version: Option<RuntimeVersion>,
+    pub max_version: Option<RuntimeVersion>,
+
+    pub opengl_compat: Option<bool>,
+    pub blocked_formats: Option<Vec<String>>,
+    pub emulated_extensions: Option<Vec<String>>,
+    pub hidden_extensions: Option<Vec<String>>,
+}
+
+impl RuntimeOverride {
+    fn matches(&self, runtime: &Runtime, version: RuntimeVersion) -> bool {
+        let name_matches = self.runtime == "*"
+            || match runtime {
+                Runtime::Other(name) => self.runtime == *name,
+                _ => self.runtime.eq_ignore_ascii_case(runtime.key()),
+            };
+        name_matches
+            && self.min_version.map_or(true, |min| version >= min)
+            && self.max_version.map_or(true, |max| version <= max)
+    }
+}
+
+impl RuntimeQuirks {
+    /// The quirks the layer knows of, none of them depend on the runtime's version or formats so
+    /// those are left to `[[runtime]]` overrides
+    pub fn builtin(runtime: &Runtime) -> Self {
+        let opengl_compat = match runtime {
+            //GLX sessions are broken on SteamVR for Linux (SteamVR-for-Linux #421, #466)
+            Runtime::SteamVR => cfg!(target_os = "linux"),
+            //WMR has no OpenGL support at all
+            Runtime::WMR => true,
+            Runtime::Oculus | Runtime::Monado | Runtime::Other(_) => false,
+        };
+
+        Self {
+            opengl_compat,
+            ..Default::default()
+        }
+    }
+
+    /// The built-in quirks with every matching `[[runtime]]` override applied in order
+    pub fn load(runtime: &Runtime, version: RuntimeVersion, overrides: &[RuntimeOverride]) -> Self {
+        let mut quirks = Self::builtin(runtime);
+        for entry in overrides
+            .iter()
+            .filter(|entry| entry.matches(runtime, version))
+        {
+            quirks.apply(entry);
+        }
+        quirks
+    }
+
+    /// The quirks to use while the runtime is unknown, only `runtime = "*"` overrides
+    /// without a version range apply
+    pub fn unknown_runtime(overrides: &[RuntimeOverride]) -> Self {
+        let mut quirks = Self::default();
+        for entry in overrides.iter().filter(|entry| {
+            entry.runtime == "*" && entry.min_version.is_none() && entry.max_version.is_none()
+        }) {
+            quirks.apply(entry);
+        }
+        quirks
+    }
+
+    fn apply(&mut self, entry: &RuntimeOverride) {
+        if let Some(opengl_compat) = entry.opengl_compat {
+            self.opengl_compat = opengl_compat;
+        }
+        if let Some(formats) = &entry.blocked_formats {
+            self.blocked_formats =
+                crate::settings::parse_formats(formats, "runtime.blocked_formats");
+        }
+        if let Some(extensions) = &entry.emulated_extensions {
+            self.emulated_extensions = extensions
+                .iter()
+                .filter(|extension| {
+                    let known = EMULATABLE_EXTENSIONS
+                        .iter()
+                        .any(|(name, _)| name == extension);
+                    if !known {
+                        warn!("The layer cannot emulate `{}`, ignoring", extension);
+                    }
+                    known
+                })
+                .cloned()
+                .collect();
+        }
+        if let Some(extensions) = &entry.hidden_extensions {
+            self.hidden_extensions = extensions.clone();
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    ffi::CStr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use graphics_interop::ImageFormat;
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::logging::{self, LogConfig};
+use crate::quirks::RuntimeOverride;
+
+pub const SETTINGS_ENV: &str = "SORENON_LAYER_SETTINGS";
+
+/// The settings last read by `Settings::load`
+static LOADED: Mutex<Option<Settings>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub log: LogSettings,
+    pub opengl: OpenGLSettings,
+    pub vulkan: VulkanSettings,
+    pub swapchain: SwapchainSettings,
+    pub composition: CompositionSettings,
+    pub instance: InstanceSettings,
+    #[serde(rename = "profile")]
+    pub profiles: Vec<Profile>,
+    #[serde(rename = "runtime")]
+    pub runtimes: Vec<RuntimeOverride>,
+}
+
+/// Settings file equivalents of the `SORENON_LAYER_LOG*` environment variables,
+/// the environment always takes priority
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    pub level: Option<String>,
+    pub output: Option<String>,
+    pub file: Option<PathBuf>,
+    pub max_size: Option<String>,
+    pub max_files: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OpenGLSettings {
+    pub mode: OpenGLMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpenGLMode {
+    /// Always run OpenGL sessions on top of a Vulkan session
+    Compat,
+    /// Forward OpenGL sessions to the runtime whenever it supports XR_KHR_opengl_enable
+    Passthrough,
+    /// Let the runtime quirk table decide, runtimes without OpenGL support are always translated
+    #[default]
+    Auto,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct VulkanSettings {
+    /// Enable VK_LAYER_KHRONOS_validation on the backend instance
+    pub validation: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SwapchainSettings {
+    /// Formats offered to the application before any others, in order
+    pub preferred_formats: Vec<String>,
+    /// Formats never offered to the application
+    pub blocked_formats: Vec<String>,
+    /// How float swapchains are brought into range when the runtime has no float formats
+    pub tone_curve: ToneCurve,
+    /// Dither tone mapped swapchains to hide banding
+    pub dither: bool,
+    /// Have OpenGL applications render at the recommended size divided by this and scale their
+    /// images back up when copying them, from 1 to 4
+    pub upscale: f32,
+}
+
+impl Default for SwapchainSettings {
+    fn default() -> Self {
+        Self {
+            preferred_formats: Vec::new(),
+            blocked_formats: Vec::new(),
+            tone_curve: ToneCurve::default(),
+            dither: true,
+            upscale: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneCurve {
+    /// Cut off everything outside of 0 to 1
+    Clamp,
+    /// x / (x + 1)
+    #[default]
+    Reinhard,
+    /// Fit of the ACES filmic curve
+    Aces,
+}
+
+/// Alpha conversions for runtimes which mishandle composition layer flags,
+/// these only apply to swapchains the layer copies
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CompositionSettings {
+    /// Premultiply layers with XR_COMPOSITION_LAYER_UNPREMULTIPLIED_ALPHA_BIT and clear the flag
+    pub premultiply_alpha: bool,
+    /// Invert the alpha of layers with XR_COMPOSITION_LAYER_INVERTED_ALPHA_BIT_EXT and clear the flag
+    pub invert_alpha: bool,
+    /// Hold released images back and copy the ones the frame's layers use at xrEndFrame in one go,
+    /// converting alpha always does this
+    pub batch_copies: bool,
+}
+
+impl CompositionSettings {
+    /// Whether layers may need their alpha converted
+    pub fn converts_alpha(&self) -> bool {
+        self.premultiply_alpha || self.invert_alpha
+    }
+
+    /// Whether images are copied at xrEndFrame, where their layers' flags are known
+    pub fn batches_copies(&self) -> bool {
+        self.batch_copies || self.converts_alpha()
+    }
+
+    /// Whether xrEndFrame has to be intercepted
+    pub fn intercepts_end_frame(&self) -> bool {
+        self.batches_copies()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InstanceSettings {
+    /// Call the runtime's xrDestroyInstance on a worker thread so a hanging runtime
+    /// cannot keep the application from exiting (SteamVR-for-Linux #422)
+    pub threaded_destroy: bool,
+    /// How long to wait for the worker thread before returning to the application
+    pub destroy_timeout_ms: u64,
+    /// Load the active runtime for calls made before any instance exists, bypassing the layers
+    /// bellow this one
+    pub load_active_runtime: bool,
+}
+
+impl Default for InstanceSettings {
+    fn default() -> Self {
+        Self {
+            threaded_destroy: false,
+            destroy_timeout_ms: 5000,
+            load_active_runtime: false,
+        }
+    }
+}
+
+impl SwapchainSettings {
+    pub fn preferred_formats(&self) -> Vec<ImageFormat> {
+        parse_formats(&self.preferred_formats, "swapchain.preferred_formats")
+    }
+
+    pub fn blocked_formats(&self) -> Vec<ImageFormat> {
+        parse_formats(&self.blocked_formats, "swapchain.blocked_formats")
+    }
+
+    /// The factor swapchains are scaled up by, `None` if they keep their size
+    pub fn upscale(&self) -> Option<f32> {
+        (self.upscale > 1.0 && self.upscale <= 4.0).then_some(self.upscale)
+    }
+}
+
+pub(crate) fn parse_formats(names: &[String], key: &str) -> Vec<ImageFormat> {
+    names
+        .iter()
+        .filter_map(|name| match name.parse() {
+            Ok(format) => Some(format),
+            Err(err) => {
+                warn!("Ignoring entry in `{}`: {}", key, err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Overrides applied on top of the global settings for matching applications
+///
+/// `application_name` and `engine_name` are patterns where `*` matches any run of characters,
+/// when both are given both have to match
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub name: Option<String>,
+    pub application_name: Option<String>,
+    pub engine_name: Option<String>,
+
+    pub opengl_mode: Option<OpenGLMode>,
+    pub vulkan_validation: Option<bool>,
+    pub preferred_formats: Option<Vec<String>>,
+    pub blocked_formats: Option<Vec<String>>,
+    pub tone_curve: Option<ToneCurve>,
+    pub dither: Option<bool>,
+    pub upscale: Option<f32>,
+    pub premultiply_alpha: Option<bool>,
+    pub invert_alpha: Option<bool>,
+    pub batch_copies: Option<bool>,
+}
+
+impl Profile {
+    pub fn matches(&self, application_name: &str, engine_name: &str) -> bool {
+        if self.application_name.is_none() && self.engine_name.is_none() {
+            return false;
+        }
+        self.application_name
+            .as_deref()
+            .map_or(true, |pattern| glob_match(pattern, application_name))
+            && self
+                .engine_name
+                .as_deref()
+                .map_or(true, |pattern| glob_match(pattern, engine_name))
+    }
+
+    pub fn label(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.application_name.as_deref())
+            .or(self.engine_name.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            text.starts_with(prefix)
+                && (0..=text.len() - prefix.len())
+                    .filter(|&i| text.is_char_boundary(prefix.len() + i))
+                    .any(|i| glob_match(rest, &text[prefix.len() + i..]))
+        }
+    }
+}
+
+impl Settings {
+    /// Finds and parses the settings file
+    ///
+    /// `SORENON_LAYER_SETTINGS` takes priority over the location handed to us by the loader,
+    /// which takes priority over `$XDG_CONFIG_HOME/sorenon_openxr_layer/settings.{toml,json}`.
+    /// A missing or broken settings file is logged and results in the default settings.
+    pub fn load(loader_location: &[i8]) -> Self {
+        let settings = Self::find_and_read(loader_location);
+        *LOADED.lock().unwrap() = Some(settings.clone());
+        settings
+    }
+
+    /// The settings of the last instance created, calls made before any instance exists read
+    /// the settings file once without the loader's location
+    pub fn current() -> Self {
+        LOADED
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Self::find_and_read(&[]))
+            .clone()
+    }
+
+    fn find_and_read(loader_location: &[i8]) -> Self {
+        let path = match Self::find(loader_location) {
+            Some(path) => path,
+            None => {
+                info!("No settings file found, using default settings");
+                return Self::default();
+            }
+        };
+
+        match Self::read(&path) {
+            Ok(settings) => {
+                info!("Loaded settings from `{}`", path.display());
+                settings
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to load settings from `{}`, using default settings: {}",
+                    path.display(),
+                    err
+                );
+                Self::default()
+            }
+        }
+    }
+
+    fn find(loader_location: &[i8]) -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os(SETTINGS_ENV) {
+            return Some(PathBuf::from(path));
+        }
+
+        if loader_location.contains(&0) {
+            let location = unsafe { CStr::from_ptr(loader_location.as_ptr()) }.to_string_lossy();
+            if !location.is_empty() {
+                let path = PathBuf::from(location.as_ref());
+                if path.is_file() {
+                    return Some(path);
+                }
+                warn!("Loader settings file `{}` does not exist", path.display());
+            }
+        }
+
+        let config_dir = dirs::config_dir()?.join("sorenon_openxr_layer");
+        ["settings.toml", "settings.json"]
+            .iter()
+            .map(|name| config_dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    fn read(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+
+        let report_unknown = |key: serde_ignored::Path| {
+            warn!("Unknown setting `{}` in `{}`", key, path.display());
+        };
+
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let mut deserializer = serde_json::Deserializer::from_str(&contents);
+            serde_ignored::deserialize(&mut deserializer, report_unknown)
+                .map_err(|err| err.to_string())
+        } else {
+            let mut deserializer = toml::Deserializer::new(&contents);
+            serde_ignored::deserialize(&mut deserializer, report_unknown)
+                .map_err(|err| err.to_string())
+        }
+    }
+
+    pub fn find_profile(&self, application_name: &str, engine_name: &str) -> Option<&Profile> {
+        self.profiles.iter().enumerate().find_map(|(i, profile)| {
+            if profile.application_name.is_none() && profile.engine_name.is_none() {
+                warn!(
+                    "Profile {} `{}` has no application_name or engine_name and never matches",
+                    i,
+                    profile.label()
+                );
+                None
+            } else if profile.matches(application_name, engine_name) {
+                Some(profile)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        if let Some(mode) = profile.opengl_mode {
+            self.opengl.mode = mode;
+        }
+        if let Some(validation) = profile.vulkan_validation {
+            self.vulkan.validation = validation;
+        }
+        if let Some(formats) = &profile.preferred_formats {
+            self.swapchain.preferred_formats = formats.clone();
+        }
+        if let Some(formats) = &profile.blocked_formats {
+            self.swapchain.blocked_formats = formats.clone();
+        }
+        if let Some(tone_curve) = profile.tone_curve {
+            self.swapchain.tone_curve = tone_curve;
+        }
+        if let Some(dither) = profile.dither {
+            self.swapchain.dither = dither;
+        }
+        if let Some(upscale) = profile.upscale {
+            self.swapchain.upscale = upscale;
+        }
+        if let Some(premultiply) = profile.premultiply_alpha {
+            self.composition.premultiply_alpha = premultiply;
+        }
+        if let Some(invert) = profile.invert_alpha {
+            self.composition.invert_alpha = invert;
+        }
+        if let Some(batch) = profile.batch_copies {
+            self.composition.batch_copies = batch;
+        }
+    }
+
+    /// Builds the logging config from the settings file with the environment applied on top
+    pub fn log_config(&self) -> LogConfig {
+        let mut config = LogConfig::default();
+        let log = &self.log;
+
+        if let Some(level) = &log.level {
+            match level.parse() {
+                Ok(filter) => config.filter = filter,
+                Err(err) => warn!("Ignoring invalid setting `log.level`: {}", err),
+            }
+        }
+        if let Some(output) = &log.output {
+            match logging::parse_outputs(output) {
+                Ok(outputs) => config.outputs = outputs,
+                Err(err) => warn!("Ignoring invalid setting `log.output`: {}", err),
+            }
+        }
+        if let Some(file) = &log.file {
+            config.file = Some(file.clone());
+        }
+        if let Some(max_size) = &log.max_size {
+            match logging::parse_size(max_size) {
+                Some(max_size) => config.max_size = max_size,
+                None => warn!("Ignoring invalid setting `log.max_size`: `{}`", max_size),
+            }
+        }
+        if let Some(max_files) = log.max_files {
+            config.max_files = max_files;
+        }
+
+        for problem in config.apply_env() {
+            warn!("Ignoring invalid log setting: {}", problem);
+        }
+
+        config
+    }
+}
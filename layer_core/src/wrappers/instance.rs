@@ -4,6 +4,9 @@ use bitflags::bitflags;
 use dashmap::DashMap;
 use openxr::sys as xr;
 
+use crate::quirks::{RuntimeQuirks, RuntimeVersion};
+use crate::settings::{OpenGLMode, Profile, Settings};
+
 use super::{session::SessionWrapper, XrHandle, XrWrapper};
 
 pub struct InstanceWrapper {
@@ -12,6 +15,40 @@ pub struct InstanceWrapper {
     pub systems: DashMap<xr::SystemId, SystemMeta>,
     pub sessions: DashMap<xr::Session, Arc<SessionWrapper>>,
     pub runtime: Runtime,
+    pub runtime_version: RuntimeVersion,
+    pub quirks: RuntimeQuirks,
+    /// The global settings with the active profile applied
+    pub settings: Settings,
+    pub profile: Option<Profile>,
+    /// Whether XR_KHR_opengl_enable was enabled on the runtime itself
+    pub native_opengl: bool,
+    /// Whether the application enabled XR_KHR_opengl_enable
+    pub opengl_enabled: bool,
+}
+
+impl InstanceWrapper {
+    /// Whether OpenGL sessions should be run on top of a Vulkan session
+    pub fn opengl_compat(&self) -> bool {
+        match self.settings.opengl.mode {
+            OpenGLMode::Compat => true,
+            OpenGLMode::Passthrough => !self.native_opengl,
+            OpenGLMode::Auto => self.quirks.opengl_compat || !self.native_opengl,
+        }
+    }
+
+    /// The factor compat swapchains are scaled up by, only OpenGL applications are copied so
+    /// only they are told to render smaller
+    pub fn upscale(&self) -> Option<f32> {
+        self.settings
+            .swapchain
+            .upscale()
+            .filter(|_| self.opengl_enabled && self.opengl_compat())
+    }
+
+    /// Whether xrEndFrame has to be intercepted to rewrite layers or submit batched copies
+    pub fn intercepts_end_frame(&self) -> bool {
+        self.settings.composition.intercepts_end_frame() || self.upscale().is_some()
+    }
 }
 
 pub struct InnerInstance {
@@ -26,6 +63,7 @@ pub struct SystemMeta {
     // pub physical_device: Option<ash::vk::PhysicalDevice>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Runtime {
     SteamVR,
     Oculus,
@@ -34,6 +72,19 @@ pub enum Runtime {
     Other(String),
 }
 
+impl Runtime {
+    /// The name used to refer to this runtime in the settings file
+    pub fn key(&self) -> &str {
+        match self {
+            Runtime::SteamVR => "steamvr",
+            Runtime::Oculus => "oculus",
+            Runtime::WMR => "wmr",
+            Runtime::Monado => "monado",
+            Runtime::Other(name) => name,
+        }
+    }
+}
+
 bitflags! {
     pub struct GraphicsEnableFlags: u8 {
         const OPENGL_GL = 0b00000001;
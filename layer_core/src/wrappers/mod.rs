@@ -40,19 +40,37 @@ pub trait XrHandle {
         Self: Sized + Hash;
 
     fn run<F>(self, f: F) -> xr::Result
+    where
+        Self: Sized + Copy + Hash + Eq + RefUnwindSafe,
+        F: FnOnce(&Arc<Self::Wrapper>) -> openxr::Result<xr::Result> + UnwindSafe,
+    {
+        self.run_with(false, f)
+    }
+
+    /// Like `run`, but runs `f` on a poisoned instance too so the handle still gets destroyed
+    fn run_destroy<F>(self, f: F) -> xr::Result
+    where
+        Self: Sized + Copy + Hash + Eq + RefUnwindSafe,
+        F: FnOnce(&Arc<Self::Wrapper>) -> openxr::Result<xr::Result> + UnwindSafe,
+    {
+        self.run_with(true, f)
+    }
+
+    fn run_with<F>(self, ignore_poison: bool, f: F) -> xr::Result
     where
         Self: Sized + Copy + Hash + Eq + RefUnwindSafe,
         F: FnOnce(&Arc<Self::Wrapper>) -> openxr::Result<xr::Result> + UnwindSafe,
     {
         match std::panic::catch_unwind(|| {
+            //Clone the wrapper out of the map so f is free to remove it
             let wrapper = match Self::all_wrappers().get(&self) {
-                Some(wrapper_ref) => wrapper_ref,
+                Some(wrapper_ref) => wrapper_ref.value().clone(),
                 None => return xr::Result::ERROR_HANDLE_INVALID,
             };
-            if wrapper.inner_instance().poison.load(Ordering::Relaxed) {
+            if !ignore_poison && wrapper.inner_instance().poison.load(Ordering::Relaxed) {
                 xr::Result::ERROR_INSTANCE_LOST
             } else {
-                match f(wrapper.value()) {
+                match f(&wrapper) {
                     Ok(res) => res,
                     Err(res) => res,
                 }
@@ -1,9 +1,9 @@
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, Mutex, Weak};
 
 use dashmap::DashMap;
 use openxr::sys as xr;
 
-use crate::graphics::{opengl::frontend::OpenGLFrontend, vulkan};
+use crate::graphics::{opengl::frontend::OpenGLFrontend, vulkan, vulkan_backend::ToneMapping};
 
 use super::{
     instance::{InnerInstance, InstanceWrapper},
@@ -17,6 +17,8 @@ pub struct SessionWrapper {
     pub inner: Arc<InnerInstance>,
     pub graphics: SessionGraphics,
     pub swapchains: DashMap<xr::Swapchain, Arc<SwapchainWrapper>>,
+    /// Images released by the application whose copy and runtime release wait for xrEndFrame
+    pub pending_releases: Mutex<Vec<(xr::Swapchain, u32)>>,
 }
 
 pub enum SessionGraphics {
@@ -25,10 +27,41 @@ pub enum SessionGraphics {
     Compat {
         frontend: Arc<OpenGLFrontend>,
         backend: Arc<vulkan::VkBackend>,
+        /// The OpenGL formats offered to the application
         swapchain_formats: Vec<i64>,
+        /// The formats the runtime can create swapchains with
+        runtime_formats: Vec<graphics_interop::ImageFormat>,
+        tone_mapping: ToneMapping,
+        /// Whether xrEndFrame may ask for alpha to be converted, which only the draw can do
+        alpha_conversion: bool,
+        /// Whether releases are held back until xrEndFrame
+        batch_copies: bool,
+        /// The factor swapchains are scaled up by when copying, see `InstanceWrapper::upscale`
+        upscale: Option<f32>,
     },
 }
 
+impl SessionWrapper {
+    pub fn batches_copies(&self) -> bool {
+        matches!(
+            self.graphics,
+            SessionGraphics::Compat {
+                batch_copies: true,
+                ..
+            }
+        )
+    }
+
+    /// Whether a release of `swapchain` is waiting for xrEndFrame
+    pub fn has_pending_release(&self, swapchain: xr::Swapchain) -> bool {
+        self.pending_releases
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|&(handle, _)| handle == swapchain)
+    }
+}
+
 impl XrWrapper for SessionWrapper {
     fn inner_instance(&self) -> &Arc<InnerInstance> {
         &self.inner
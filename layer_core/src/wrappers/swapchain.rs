@@ -1,7 +1,10 @@
 use std::{
     cell::RefCell,
     collections::VecDeque,
-    sync::{Arc, Mutex, Weak},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
 };
 
 use dashmap::DashMap;
@@ -16,9 +19,14 @@ pub struct SwapchainWrapper {
     pub inner: Arc<InnerInstance>,
     pub width: u32,
     pub height: u32,
+    /// The size of the runtime's images, larger than the application's when upscaling
+    pub runtime_width: u32,
+    pub runtime_height: u32,
     pub layers: u32,
     pub graphics: SwapchainGraphics,
-    pub acquired_images: Mutex<RefCell<VecDeque<u32>>>,
+    /// Acquired image indices, oldest first, and whether they have been waited on
+    pub acquired_images: Mutex<RefCell<VecDeque<(u32, bool)>>>,
+    pub graphics_destroyed: AtomicBool,
 }
 
 pub enum SwapchainGraphics {
@@ -26,6 +34,7 @@ pub enum SwapchainGraphics {
     Compat {
         frontend: Box<dyn SwapchainFrontend>,
         interop: Vec<(graphics_interop::InteropHandle, u64)>,
+        interop_semaphores: Vec<graphics_interop::InteropHandle>,
         backend: Box<dyn SwapchainBackend>,
     },
 }
@@ -43,44 +52,91 @@ pub trait SwapchainFrontend {
     fn destroy(&self);
 }
 
+bitflags::bitflags! {
+    /// Changes made to alpha while copying images to the runtime
+    pub struct AlphaConversion: u32 {
+        /// Replace alpha with one minus alpha
+        const INVERT      = 0b01;
+        /// Multiply color by the (inverted) alpha
+        const PREMULTIPLY = 0b10;
+    }
+}
+
 pub trait SwapchainBackend {
-    fn get_external_memory_handles(&self) -> Vec<(graphics_interop::InteropHandle, u64)>;
+    fn get_external_memory_handles(
+        &self,
+    ) -> openxr::Result<Vec<(graphics_interop::InteropHandle, u64)>>;
+
+    /// One semaphore per image for the frontend to signal, empty if the backend doesn't wait on any
+    fn get_external_semaphore_handles(
+        &self,
+    ) -> openxr::Result<Vec<graphics_interop::InteropHandle>>;
+
+    /// Stops waiting on semaphores the frontend couldn't import, before any image is released
+    fn drop_semaphores(&mut self);
+
+    /// Submits the copy of image `index` without waiting for it
+    fn release_image(&self, index: usize) -> openxr::Result<()>;
+
+    /// Waits for the last copy out of image `index` and marks its copy as outstanding until
+    /// `queue_release`, for releases held back until the session's next batch
+    fn hold_release(&self, index: usize) -> openxr::Result<()>;
+
+    /// Queues the copy of held image `index` for the session's next batch, the image still has
+    /// to be handed back to the runtime afterwards
+    fn queue_release(&self, index: usize);
 
-    fn release_image(&self, index: usize);
+    /// Blocks until the last copy out of image `index` is done so the application can write to it,
+    /// returns false if that took longer than `timeout` nanoseconds or the copy is still held
+    fn wait_image(&self, index: usize, timeout: u64) -> bool;
+
+    /// Applies `conversion` to every image released from now on,
+    /// returns false if the copy cannot convert this swapchain's alpha
+    fn set_alpha_conversion(&self, conversion: AlphaConversion) -> bool;
 
     fn destroy(&self);
 }
 
-impl Drop for SwapchainWrapper {
-    fn drop(&mut self) {
+impl SwapchainWrapper {
+    /// Frees the layer's graphics resources, these reference the runtime images so this has to
+    /// happen before the runtime swapchain is destroyed
+    pub fn destroy_graphics(&self) {
+        if self.graphics_destroyed.swap(true, Ordering::AcqRel) {
+            return;
+        }
         if let SwapchainGraphics::Compat {
             frontend,
+            #[cfg(target_os = "windows")]
             interop,
+            #[cfg(target_os = "windows")]
+            interop_semaphores,
             backend,
+            ..
         } = &self.graphics
         {
             frontend.destroy();
-            for &(handle, _) in interop {
-                #[cfg(target_os = "windows")]
+            //On Linux the fds are owned by the OpenGL objects once imported
+            #[cfg(target_os = "windows")]
+            for handle in interop
+                .iter()
+                .map(|&(handle, _)| handle)
+                .chain(interop_semaphores.iter().copied())
+            {
                 unsafe {
                     winapi::um::handleapi::CloseHandle(handle);
                 }
-                #[cfg(target_os = "linux")]
-                unsafe {
-                    if libc::close(handle) == -1 {
-                        error!(
-                            "Failed to close swapchain fd `{}` with error `{:X}`",
-                            handle,
-                            *libc::__errno_location()
-                        )
-                    }
-                }
             }
             backend.destroy();
         }
     }
 }
 
+impl Drop for SwapchainWrapper {
+    fn drop(&mut self) {
+        self.destroy_graphics();
+    }
+}
+
 impl XrWrapper for SwapchainWrapper {
     fn inner_instance(&self) -> &Arc<InnerInstance> {
         &self.inner
@@ -1,12 +1,8 @@
-use std::{ffi::CStr, fs::File, path::Path, sync::atomic::AtomicBool};
+use std::ffi::CStr;
 
 use layer_core::loader_interfaces::*;
 use log::{debug, error, info};
 use openxr::sys as xr;
-use simplelog::*;
-
-//TODO set up a safer logging system
-static LOGGER_LOADED: AtomicBool = AtomicBool::new(false);
 
 #[no_mangle]
 #[allow(clippy::missing_safety_doc)]
@@ -15,26 +11,7 @@ pub unsafe extern "system" fn xrNegotiateLoaderApiLayerInterface(
     layer_name: *const i8,
     layer_request: *mut XrNegotiateApiLayerRequest,
 ) -> xr::Result {
-    if !LOGGER_LOADED.load(std::sync::atomic::Ordering::Relaxed) {
-        let workspace_path = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
-
-        CombinedLogger::init(vec![
-            TermLogger::new(
-                LevelFilter::Debug,
-                Config::default(),
-                TerminalMode::Mixed,
-                ColorChoice::Auto,
-            ),
-            WriteLogger::new(
-                LevelFilter::Trace,
-                Config::default(),
-                File::create(workspace_path.join("log.txt")).unwrap(),
-            ),
-        ])
-        .unwrap();
-
-        LOGGER_LOADED.store(true, std::sync::atomic::Ordering::Relaxed)
-    }
+    layer_core::logging::initialize();
 
     info!("Initializing layer");
 
@@ -0,0 +1,59 @@
+#version 450
+
+layout(location = 0) in vec3 texCoords;
+
+layout(location = 0) out vec4 outColor;
+
+layout(set = 0, binding = 0) uniform texture2DArray tex;
+layout(set = 0, binding = 1) uniform sampler texSampler;
+
+layout(push_constant) uniform Convert {
+    // 0 none, 1 clamp, 2 reinhard, 3 aces
+    uint curve;
+    uint encodeSrgb;
+    // One step of the target format, 0 disables dithering
+    float ditherScale;
+    // Bit 0 inverts alpha, bit 1 premultiplies color by alpha
+    uint alpha;
+} convert;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 x) {
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+void main() {
+    vec4 color = texture(sampler2DArray(tex, texSampler), texCoords);
+    vec3 rgb = color.rgb;
+    float alpha = color.a;
+
+    if (convert.curve != 0u) {
+        rgb = max(rgb, vec3(0.0));
+        if (convert.curve == 2u) {
+            rgb = rgb / (rgb + vec3(1.0));
+        } else if (convert.curve == 3u) {
+            rgb = aces(rgb);
+        }
+        rgb = clamp(rgb, vec3(0.0), vec3(1.0));
+        alpha = clamp(alpha, 0.0, 1.0);
+    }
+
+    if ((convert.alpha & 1u) != 0u) {
+        alpha = 1.0 - alpha;
+    }
+    // Premultiplied in linear space, before any sRGB encoding
+    if ((convert.alpha & 2u) != 0u) {
+        rgb *= alpha;
+    }
+
+    // Encoded here instead of by an sRGB view so the dither is even in the stored values
+    if (convert.encodeSrgb != 0u) {
+        rgb = mix(rgb * 12.92, 1.055 * pow(rgb, vec3(1.0 / 2.4)) - 0.055, step(vec3(0.0031308), rgb));
+    }
+
+    // Interleaved gradient noise
+    float noise = fract(52.9829189 * fract(dot(gl_FragCoord.xy, vec2(0.06711056, 0.00583715)))) - 0.5;
+    rgb += vec3(noise * convert.ditherScale);
+
+    outColor = vec4(rgb, alpha);
+}